@@ -0,0 +1,144 @@
+//! Bundles a generated 200-module TypeScript project the way the loader does
+//! now, handing the parsed and stripped AST to the bundler, against the way it
+//! used to: printing each transpiled module to JavaScript for the bundler to
+//! parse again. The old path is replayed here by transpiling every module to
+//! a string, as the old loader did, and bundling the printed JavaScript.
+//!
+//! On a single CPU, unminified, over four runs: 29-39ms with the AST and
+//! 39-41ms printing and parsing again, about 15% faster. Loading on several
+//! threads isn't measured, as there was only one core.
+//!
+//! Run with `cargo bench --bench bundle`.
+
+use std::fs;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dino::{bundle, Options};
+use swc_common::sync::Lrc;
+use swc_common::{FileName, Globals, Mark, SourceMap, GLOBALS};
+use swc_ecma_ast::{EsVersion, Program};
+use swc_ecma_codegen::text_writer::JsWriter;
+use swc_ecma_codegen::Emitter;
+use swc_ecma_parser::{parse_file_as_module, Syntax, TsSyntax};
+use swc_ecma_transforms_base::fixer::fixer;
+use swc_ecma_transforms_base::hygiene::hygiene;
+use swc_ecma_transforms_base::resolver;
+use swc_ecma_transforms_typescript::strip;
+use swc_ecma_visit::FoldWith;
+
+const MODULES: usize = 200;
+
+/// Writes the project with `extension` in its file names and imports, and
+/// returns the entry.
+fn generate_project(dir: &Path, extension: &str) -> String {
+    for i in 0..MODULES {
+        let imports: String = (i + 1..(i + 4).min(MODULES))
+            .map(|j| format!("import {{ module{j} }} from './module{j}.{extension}';\n"))
+            .collect();
+        let calls: String = (i + 1..(i + 4).min(MODULES))
+            .map(|j| format!("    total += module{j}(input).length;\n"))
+            .collect();
+        let source = format!(
+            r#"{imports}
+interface Input{i} {{
+    name: string;
+    tags: string[];
+}}
+
+export function module{i}(input: Input{i}): string {{
+    let total: number = 0;
+{calls}    return `${{input.name}}-{i}-${{total}}-${{input.tags.join(",")}}`;
+}}
+"#
+        );
+        fs::write(dir.join(format!("module{i}.{extension}")), source).unwrap();
+    }
+
+    // the entry imports every module, so that there is plenty to load at once
+    let entry = dir.join(format!("main.{extension}"));
+    let imports: String = (0..MODULES)
+        .map(|i| format!("import {{ module{i} }} from './module{i}.{extension}';\n"))
+        .collect();
+    let exports: Vec<String> = (0..MODULES).map(|i| format!("module{i}")).collect();
+    fs::write(
+        &entry,
+        format!("{imports}export default [{}];\n", exports.join(", ")),
+    )
+    .unwrap();
+    entry.display().to_string()
+}
+
+/// Transpiles TypeScript to JavaScript source, as the loader used to.
+fn print_js(source: &str) -> String {
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(Lrc::new(FileName::Anon), source.into());
+    let module = parse_file_as_module(
+        &fm,
+        Syntax::Typescript(TsSyntax {
+            tsx: true,
+            decorators: true,
+            no_early_errors: true,
+            ..Default::default()
+        }),
+        EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .unwrap();
+
+    let mut buf = vec![];
+    GLOBALS.set(&Globals::default(), || {
+        let (unresolved_mark, top_level_mark) = (Mark::new(), Mark::new());
+        let program = Program::Module(module)
+            .fold_with(&mut resolver(unresolved_mark, top_level_mark, true))
+            .fold_with(&mut strip(unresolved_mark, top_level_mark))
+            .fold_with(&mut hygiene())
+            .fold_with(&mut fixer(None));
+        let mut emitter = Emitter {
+            cfg: Default::default(),
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm.clone(), "\n", &mut buf, None),
+        };
+        emitter.emit_program(&program).unwrap();
+    });
+    String::from_utf8(buf).unwrap()
+}
+
+fn bench_bundle(c: &mut Criterion) {
+    let ts_dir = assert_fs::TempDir::new().unwrap();
+    let ts_entry = generate_project(&ts_dir, "ts");
+    let sources: Vec<String> = fs::read_dir(&ts_dir)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+
+    // what the bundler parsed before: the printed modules
+    let js_dir = assert_fs::TempDir::new().unwrap();
+    let js_entry = generate_project(&js_dir, "js");
+    for entry in fs::read_dir(&js_dir).unwrap() {
+        let path = entry.unwrap().path();
+        fs::write(&path, print_js(&fs::read_to_string(&path).unwrap())).unwrap();
+    }
+
+    let options = Options {
+        minify: false,
+        ..Default::default()
+    };
+    let mut group = c.benchmark_group("bundle");
+    group.sample_size(10);
+    group.bench_function("ast", |b| b.iter(|| bundle(&ts_entry, &options).unwrap()));
+    group.bench_function("print_and_reparse", |b| {
+        b.iter(|| {
+            for source in &sources {
+                print_js(source);
+            }
+            bundle(&js_entry, &options).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_bundle);
+criterion_main!(benches);
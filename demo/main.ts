@@ -0,0 +1,9 @@
+async function hello(req: object): Promise<object> {
+  return {
+    status: 200,
+    headers: { 'content-type': 'application/json' },
+    body: JSON.stringify(req),
+  };
+}
+
+export { hello };
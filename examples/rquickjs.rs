@@ -15,7 +15,7 @@ async fn main() -> Result<()> {
         let fun = Function::new(ctx.clone(), log)?.with_name("log")?;
         global.set("log", fun)?;
 
-        ctx.eval(r#"log("Hello, World!")"#)?;
+        ctx.eval::<(), _>(r#"log("Hello, World!")"#)?;
 
         let result: String = ctx.eval_file("examples/rquickjs.js").unwrap();
         // Print the result
@@ -1,7 +1,8 @@
 import { execute } from './lib.ts';
 
-async function main(): Promise<string> {
-  return await execute('world');
+async function main(): Promise<void> {
+  console.log('Executing main');
+  console.log(await execute('world'));
 }
 
 export default main;
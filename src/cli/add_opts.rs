@@ -0,0 +1,81 @@
+use std::env;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+
+use super::{
+    routes::{add_route, handler_name, validate_route_path, METHODS},
+    CmdExector,
+};
+use crate::RouteConfig;
+
+#[derive(Debug, Parser)]
+pub struct AddOpts {
+    #[command(subcommand)]
+    pub cmd: AddCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AddCommand {
+    /// Add a route to config.yml and a handler stub exported from main.ts
+    Route(AddRouteOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct AddRouteOpts {
+    /// HTTP method: GET, POST, PUT, PATCH or DELETE
+    #[arg(value_parser = parse_method)]
+    pub method: String,
+    /// Path of the route, e.g. `/api/users/{id}`
+    #[arg(value_parser = parse_path)]
+    pub path: String,
+    /// Name of the handler, derived from the method and path by default
+    #[arg(long)]
+    pub handler: Option<String>,
+    /// Put the handler in its own file under `handlers/` instead of main.ts
+    #[arg(long)]
+    pub new_file: bool,
+}
+
+impl CmdExector for AddOpts {
+    async fn execute(self) -> Result<()> {
+        let path = env::current_dir()?;
+        match self.cmd {
+            AddCommand::Route(opts) => {
+                let route = RouteConfig {
+                    handler: opts
+                        .handler
+                        .unwrap_or_else(|| handler_name(&opts.method, &opts.path)),
+                    path: opts.path,
+                    method: opts.method,
+                };
+                for file in add_route(&path, &route, opts.new_file)? {
+                    let file = file.strip_prefix(&path).unwrap_or(&file);
+                    println!("{} {}", "Updated".green(), file.display());
+                }
+                println!(
+                    "{} {} {} handled by {}",
+                    "Added".green(),
+                    route.method,
+                    route.path,
+                    route.handler
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_method(value: &str) -> Result<String> {
+    let method = value.to_uppercase();
+    match METHODS.contains(&method.as_str()) {
+        true => Ok(method),
+        false => Err(anyhow!("expected one of {}", METHODS.join(", "))),
+    }
+}
+
+fn parse_path(value: &str) -> Result<String> {
+    validate_route_path(value).map_err(|e| anyhow!("path {e}"))?;
+    Ok(value.to_string())
+}
@@ -1,16 +1,67 @@
-use std::env;
+use std::{env, path::PathBuf};
 
+use anyhow::{anyhow, Result};
 use clap::Parser;
+use swc_ecma_ast::EsVersion;
 
 use super::{build_project, CmdExector};
+use crate::{BuildConfig, LegalComments, MinifyConfig, OutputFormat, DEFAULT_PROFILE};
 
 #[derive(Debug, Parser)]
-pub struct BuildOpts {}
+pub struct BuildOpts {
+    /// Build profile: `dev`, `release` or one defined in config.yml
+    #[arg(long, default_value = DEFAULT_PROFILE)]
+    pub profile: String,
+    /// Replace a global expression with a JS expression, e.g. `__VERSION__='"1.0.0"'`
+    #[arg(long = "define", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub define: Vec<(String, String)>,
+    /// Load `process.env.*` values from this file instead of `.env`
+    #[arg(long, value_name = "FILE")]
+    pub env_file: Option<PathBuf>,
+    /// Strip whitespace and compress and mangle the bundle, whatever the profile says
+    #[arg(long)]
+    pub minify: bool,
+    /// Module format of the bundle, overriding the profile's
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// ES version to compile down to, e.g. `es5`, `es2017` or `esnext`
+    #[arg(long, value_parser = parse_target)]
+    pub target: Option<EsVersion>,
+    /// Keep license comments in the bundle, move them to a LICENSES.txt next to it, or drop them
+    #[arg(long, value_enum)]
+    pub legal_comments: Option<LegalComments>,
+    /// Write a report of the bundled modules to `build/<profile>/analyze.{json,html}`
+    #[arg(long)]
+    pub analyze: bool,
+}
 
 impl CmdExector for BuildOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let path = env::current_dir()?;
-        build_project(&path)?;
+        let overrides = BuildConfig {
+            define: self.define.into_iter().collect(),
+            env_file: self.env_file,
+            minify: self.minify.then_some(MinifyConfig::Enabled(true)),
+            format: self.format,
+            target: self.target,
+            legal_comments: self.legal_comments,
+            analyze: self.analyze,
+            ..Default::default()
+        };
+        build_project(&path, &self.profile, &overrides)?;
         Ok(())
     }
 }
+
+pub(super) fn parse_key_value(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow!("expected KEY=VALUE, got \"{value}\"")),
+    }
+}
+
+fn parse_target(value: &str) -> Result<EsVersion> {
+    serde_json::from_value(serde_json::Value::String(value.to_lowercase())).map_err(|_| {
+        anyhow!("unknown target \"{value}\", expected es3, es5, es2015 ... es2022 or esnext")
+    })
+}
@@ -0,0 +1,76 @@
+use std::{env, fs, path::Path};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+
+use super::{CmdExector, Manifest, BUILD_DIR_NAME};
+use crate::{validate_profile_name, ProjectConfig};
+
+#[derive(Debug, Parser)]
+pub struct CleanOpts {
+    /// Only remove the builds of this profile
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+impl CmdExector for CleanOpts {
+    async fn execute(self) -> Result<()> {
+        let path = env::current_dir()?;
+        clean_project(&path, self.profile.as_deref())
+    }
+}
+
+fn clean_project(path: &Path, profile: Option<&str>) -> Result<()> {
+    let build_path = path.join(BUILD_DIR_NAME);
+    if !build_path.is_dir() {
+        return Ok(());
+    }
+
+    match profile {
+        Some(profile) => {
+            validate_profile_name(profile)?;
+            let mut manifest = Manifest::load(&build_path)?;
+            // profiles that were removed from config.yml can still be cleaned
+            if !manifest.profiles.contains_key(profile)
+                && ProjectConfig::load(path)?.profile(profile).is_err()
+            {
+                bail!("Unknown build profile \"{profile}\"");
+            }
+            manifest.remove_profile(&build_path, profile)?;
+            manifest.save(&build_path)?;
+        }
+        None => fs::remove_dir_all(&build_path)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_project_should_only_remove_profiles() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(temp_dir.join("main.ts"), "export {};\n")?;
+        let build_path = temp_dir.join(BUILD_DIR_NAME);
+        for dir in ["dev", "release", ".cache"] {
+            fs::create_dir_all(build_path.join(dir))?;
+        }
+
+        for profile in ["..", "/", ".cache", "", "unknown"] {
+            assert!(
+                clean_project(&temp_dir, Some(profile)).is_err(),
+                "{profile}"
+            );
+        }
+        assert!(temp_dir.join("main.ts").is_file());
+        for dir in ["dev", "release", ".cache"] {
+            assert!(build_path.join(dir).is_dir(), "{dir}");
+        }
+
+        clean_project(&temp_dir, Some("release"))?;
+        assert!(!build_path.join("release").exists());
+        assert!(build_path.join("dev").is_dir());
+        Ok(())
+    }
+}
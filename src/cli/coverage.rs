@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use super::BUILD_DIR_NAME;
+use crate::{BranchLocation, CoverageCounters, CoverageMap};
+
+/// `--coverage` output goes to `build/coverage`.
+const COVERAGE_DIR_NAME: &str = "coverage";
+const LCOV_FILE_NAME: &str = "lcov.info";
+
+/// Counters summed over the workers that ran instrumented bundles.
+#[derive(Debug, Default)]
+pub(super) struct CoverageReport {
+    files: BTreeMap<String, FileCoverage>,
+}
+
+#[derive(Debug)]
+struct FileCoverage {
+    map: CoverageMap,
+    counters: CoverageCounters,
+}
+
+impl CoverageReport {
+    /// Adds the modules of a bundle, see `BundleOutput::coverage`.
+    pub fn add_maps(&mut self, maps: &BTreeMap<String, CoverageMap>) {
+        for (file, map) in maps {
+            self.files
+                .entry(file.clone())
+                .or_insert_with(|| FileCoverage {
+                    map: map.clone(),
+                    counters: CoverageCounters {
+                        s: vec![0; map.statements.len()],
+                        b: vec![0; map.branches.len()],
+                    },
+                });
+        }
+    }
+
+    /// Adds the counters of a worker, see `JsWorker::coverage`.
+    pub fn add_counters(&mut self, counters: &BTreeMap<String, CoverageCounters>) {
+        for (file, counters) in counters {
+            let Some(coverage) = self.files.get_mut(file) else {
+                continue;
+            };
+            for (total, count) in [
+                (&mut coverage.counters.s, &counters.s),
+                (&mut coverage.counters.b, &counters.b),
+            ] {
+                for (total, count) in total.iter_mut().zip(count) {
+                    *total += count;
+                }
+            }
+        }
+    }
+
+    /// Keeps only the files `f` returns true for.
+    pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+        self.files.retain(|file, _| f(file));
+    }
+
+    /// Writes the report in the lcov format to `build/coverage/lcov.info`,
+    /// with paths relative to `root`, and returns the file.
+    pub fn write_lcov(&self, root: &Path) -> Result<PathBuf> {
+        let dir = root.join(BUILD_DIR_NAME).join(COVERAGE_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+        let file = dir.join(LCOV_FILE_NAME);
+        fs::write(&file, self.to_lcov(root))?;
+        Ok(file)
+    }
+
+    fn to_lcov(&self, root: &Path) -> String {
+        let mut out = String::new();
+        for (file, coverage) in &self.files {
+            let lines = coverage.lines();
+            let branches = coverage.branches();
+            let _ = writeln!(out, "TN:\nSF:{}", relative(root, file));
+            for (line, count) in &lines {
+                let _ = writeln!(out, "DA:{line},{count}");
+            }
+            let _ = writeln!(out, "LF:{}", lines.len());
+            let _ = writeln!(out, "LH:{}", lines.values().filter(|c| **c > 0).count());
+            for (location, taken) in &branches {
+                // `-` when the decision itself was never reached
+                let taken = match taken {
+                    Some(count) => count.to_string(),
+                    None => "-".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "BRDA:{},{},{},{taken}",
+                    location.line, location.block, location.branch
+                );
+            }
+            let _ = writeln!(out, "BRF:{}", branches.len());
+            let _ = writeln!(
+                out,
+                "BRH:{}",
+                branches.iter().filter(|(_, c)| c.unwrap_or(0) > 0).count()
+            );
+            out.push_str("end_of_record\n");
+        }
+        out
+    }
+
+    /// A table of the line and branch coverage of each file, with the lines
+    /// that never ran.
+    pub fn summary(&self, root: &Path) -> String {
+        let rows: Vec<_> = self
+            .files
+            .iter()
+            .map(|(file, coverage)| {
+                let lines = coverage.lines();
+                let branches = coverage.branches();
+                let uncovered: Vec<_> = lines
+                    .iter()
+                    .filter(|(_, count)| **count == 0)
+                    .map(|(line, _)| *line)
+                    .collect();
+                (
+                    relative(root, file),
+                    percent(lines.values().filter(|c| **c > 0).count(), lines.len()),
+                    percent(
+                        branches.iter().filter(|(_, c)| c.unwrap_or(0) > 0).count(),
+                        branches.len(),
+                    ),
+                    line_ranges(&uncovered),
+                )
+            })
+            .collect();
+
+        let width = rows
+            .iter()
+            .map(|(file, ..)| file.len())
+            .chain([4])
+            .max()
+            .unwrap_or_default();
+        let mut out = format!(
+            "{:width$}  {:>8}  {:>8}  {}\n",
+            "File", "Lines", "Branches", "Uncovered lines",
+        );
+        for (file, lines, branches, uncovered) in rows {
+            let row = format!(
+                "{file:width$}  {:>8}  {:>8}  {uncovered}",
+                format!("{lines:.1}%"),
+                format!("{branches:.1}%"),
+            );
+            out.push_str(row.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl FileCoverage {
+    /// Runs of each line with statements, counting the most run statement.
+    fn lines(&self) -> BTreeMap<u32, u64> {
+        let mut lines = BTreeMap::new();
+        for (line, count) in self.map.statements.iter().zip(&self.counters.s) {
+            let total = lines.entry(*line).or_insert(0);
+            *total = (*total).max(*count);
+        }
+        lines
+    }
+
+    /// Runs of each branch, `None` when none of its decision's branches ran.
+    fn branches(&self) -> Vec<(&BranchLocation, Option<u64>)> {
+        let reached: BTreeSet<_> = self
+            .map
+            .branches
+            .iter()
+            .zip(&self.counters.b)
+            .filter(|(_, count)| **count > 0)
+            .map(|(location, _)| location.block)
+            .collect();
+        self.map
+            .branches
+            .iter()
+            .zip(&self.counters.b)
+            .map(|(location, count)| {
+                (
+                    location,
+                    reached.contains(&location.block).then_some(*count),
+                )
+            })
+            .collect()
+    }
+}
+
+fn relative(root: &Path, file: &str) -> String {
+    Path::new(file)
+        .strip_prefix(root)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| file.to_string())
+}
+
+/// Nothing to cover counts as covered.
+fn percent(covered: usize, total: usize) -> f64 {
+    match total {
+        0 => 100.0,
+        _ => covered as f64 * 100.0 / total as f64,
+    }
+}
+
+/// Joins sorted lines into ranges, e.g. `1-3, 7`.
+fn line_ranges(lines: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{start}-{end}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_report_should_write_lcov() {
+        let map = CoverageMap {
+            statements: vec![1, 2, 2, 3, 5, 6],
+            branches: vec![
+                BranchLocation {
+                    line: 2,
+                    block: 0,
+                    branch: 0,
+                },
+                BranchLocation {
+                    line: 2,
+                    block: 0,
+                    branch: 1,
+                },
+                BranchLocation {
+                    line: 5,
+                    block: 1,
+                    branch: 0,
+                },
+                BranchLocation {
+                    line: 5,
+                    block: 1,
+                    branch: 1,
+                },
+            ],
+        };
+        let mut report = CoverageReport::default();
+        report.add_maps(&BTreeMap::from([
+            ("/app/main.ts".to_string(), map.clone()),
+            ("/app/main.test.ts".to_string(), map),
+        ]));
+        let counters = CoverageCounters {
+            s: vec![1, 2, 0, 0, 0, 0],
+            b: vec![2, 0, 0, 0],
+        };
+        report.add_counters(&BTreeMap::from([(
+            "/app/main.ts".to_string(),
+            counters.clone(),
+        )]));
+        report.add_counters(&BTreeMap::from([
+            ("/app/main.ts".to_string(), counters),
+            // not in a bundle's maps
+            ("/app/other.ts".to_string(), CoverageCounters::default()),
+        ]));
+        report.retain(|file| !file.ends_with(".test.ts"));
+
+        let root = Path::new("/app");
+        assert_eq!(
+            report.to_lcov(root),
+            "TN:\nSF:main.ts\nDA:1,2\nDA:2,4\nDA:3,0\nDA:5,0\nDA:6,0\nLF:5\nLH:2\n\
+             BRDA:2,0,0,4\nBRDA:2,0,1,0\nBRDA:5,1,0,-\nBRDA:5,1,1,-\nBRF:4\nBRH:1\nend_of_record\n"
+        );
+        assert_eq!(
+            report.summary(root),
+            "File        Lines  Branches  Uncovered lines\n\
+             main.ts     40.0%     25.0%  3, 5-6\n"
+        );
+    }
+}
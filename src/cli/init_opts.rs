@@ -1,52 +1,699 @@
-use anyhow::Result;
-use askama::Template;
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
-use dialoguer::Input;
-use git2::Repository;
-use std::{fs, path::Path};
+use colored::Colorize;
+use dialoguer::{BasicHistory, Completion, Confirm, FuzzySelect, Input};
+use git2::{Config, IndexAddOption, Repository, RepositoryInitOptions, Signature};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
 
-use crate::CmdExector;
+use super::{
+    build_opts::parse_key_value,
+    routes::{declares, find_route, handler_name, validate_handler, validate_route_path, METHODS},
+    template::{ProjectTemplate, BUILTIN_TEMPLATES, DEFAULT_TEMPLATE},
+    ENTRY_FILE_NAME,
+};
+use crate::{CmdExector, ProjectConfig, RouteConfig, CONFIG_FILE_NAME};
+
+/// Used when git config doesn't set `init.defaultBranch`.
+const DEFAULT_BRANCH: &str = "main";
+const PRE_COMMIT_HOOK: &str = "#!/bin/sh
+# Installed by `dino init`: checks that the project builds and its tests pass before committing.
+set -e
+dino build
+dino test
+";
 
 #[derive(Debug, Parser)]
-pub struct InitOpts {}
+pub struct InitOpts {
+    /// Project name, asked for when missing and stdin is a terminal
+    pub name: Option<String>,
+    /// Starter to scaffold the project from: `default`, `api`, `site`, `job`,
+    /// `test`, or a directory or git URL of `.j2` templates. Asked for when
+    /// interactive, `default` otherwise
+    #[arg(long)]
+    pub template: Option<String>,
+    /// Set a template variable instead of asking for it, e.g. `resource=posts`
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub vars: Vec<(String, String)>,
+    /// Directory to create the project in, defaults to `./NAME`, or the
+    /// current directory when it is empty
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+    /// Don't initialize a git repository
+    #[arg(long)]
+    pub no_git: bool,
+    /// Name of the initial branch, defaults to git's `init.defaultBranch` or `main`
+    #[arg(long, conflicts_with = "no_git")]
+    pub branch: Option<String>,
+    /// Commit the scaffolded files, authored as configured in git
+    #[arg(long, conflicts_with = "no_git")]
+    pub commit: bool,
+    /// Install a pre-commit hook checking the project
+    #[arg(long, conflicts_with = "no_git")]
+    pub hook: bool,
+    /// Scaffold into a directory that isn't empty, overwriting files
+    #[arg(long)]
+    pub force: bool,
+    /// Never prompt, the project is named after `--dir`, or the current
+    /// directory when it is empty, unless NAME is given
+    #[arg(long, short)]
+    pub yes: bool,
+}
 
 impl CmdExector for InitOpts {
     async fn execute(self) -> Result<()> {
-        let name: String = Input::new().with_prompt("Project name").interact_text()?;
+        let current_dir = env::current_dir()?;
+        let interactive = !self.yes && io::stdin().is_terminal();
+        let name = match self.name {
+            Some(name) => name,
+            None if interactive => Input::new()
+                .with_prompt("Project name")
+                .validate_with(|name: &String| validate_project_name(name))
+                .interact_text()?,
+            None => default_name(self.dir.as_deref(), &current_dir)?,
+        };
+        validate_project_name(&name)
+            .map_err(|e| anyhow!("Invalid project name \"{name}\": {e}"))?;
 
-        let current_dir = Path::new(".");
-        if fs::read_dir(current_dir)?.next().is_none() {
-            init_project(name, current_dir)?;
-        } else {
-            init_project(name.clone(), &current_dir.join(name))?;
+        let path = match self.dir {
+            Some(dir) => dir,
+            None if is_empty_dir(&current_dir)? => current_dir,
+            None => current_dir.join(&name),
+        };
+        check_target(&path, self.force)?;
+
+        let template = match self.template {
+            Some(template) => template,
+            None if interactive => select_template()?,
+            None => DEFAULT_TEMPLATE.to_string(),
+        };
+        let template = ProjectTemplate::load(&template)?;
+        let mut vars: BTreeMap<_, _> = self.vars.into_iter().collect();
+        for var in &template.manifest.variables {
+            if vars.contains_key(&var.name) {
+                continue;
+            }
+            let value = match &var.prompt {
+                Some(prompt) if interactive => Input::new()
+                    .with_prompt(prompt)
+                    .default(var.default.clone())
+                    .interact_text()?,
+                _ => var.default.clone(),
+            };
+            vars.insert(var.name.clone(), value);
         }
+        vars.insert("name".to_string(), name.clone());
+
+        let extras = match interactive {
+            true => ask_extras(&template, &vars)?,
+            false => InitExtras::default(),
+        };
+
+        let mut git = None;
+        if !self.no_git && create_repo(&path, interactive)? {
+            let mut setup = GitSetup {
+                branch: match self.branch {
+                    Some(branch) => branch,
+                    None => default_branch()?,
+                },
+                remote: None,
+                author: None,
+                hook: self.hook,
+            };
+            let commit = match interactive {
+                true => ask_git_setup(&mut setup)?,
+                false => self.commit,
+            };
+            if commit {
+                setup.author = Some(configured_author()?);
+            }
+            git = Some(setup);
+        }
+
+        init_project(&path, &template, &vars, &extras, git.as_ref(), self.force)?;
+        println!("{} {name} in {}", "Created".green(), path.display());
         Ok(())
     }
 }
 
-fn init_project(name: String, path: &Path) -> Result<()> {
+/// What the init wizard adds to the template.
+#[derive(Debug, Default)]
+struct InitExtras {
+    /// Routes added to config.yml, with a handler stub each in main.ts.
+    routes: Vec<RouteConfig>,
+    /// Add a `main.test.ts` testing the routes.
+    tests: bool,
+    /// Add a `tsconfig.json` for editors.
+    tsconfig: bool,
+}
+
+/// How `dino init` sets up the project's git repository.
+struct GitSetup {
+    /// Name of the initial branch.
+    branch: String,
+    /// URL of the `origin` remote.
+    remote: Option<String>,
+    /// Author of the initial commit of the scaffolded files, none skips it.
+    author: Option<Signature<'static>>,
+    /// Install [`PRE_COMMIT_HOOK`].
+    hook: bool,
+}
+
+fn select_template() -> Result<String> {
+    let mut items: Vec<_> = BUILTIN_TEMPLATES
+        .iter()
+        .map(|(name, _)| {
+            let template = ProjectTemplate::load(name)?;
+            Ok(format!("{name:<10}{}", template.manifest.description))
+        })
+        .collect::<Result<_>>()?;
+    items.push("other     A directory or git URL".to_string());
+
+    let index = FuzzySelect::new()
+        .with_prompt("Template")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    match BUILTIN_TEMPLATES.get(index) {
+        Some((name, _)) => Ok(name.to_string()),
+        None => Ok(Input::new()
+            .with_prompt("Template directory or git URL")
+            .interact_text()?),
+    }
+}
+
+fn ask_extras(template: &ProjectTemplate, vars: &BTreeMap<String, String>) -> Result<InitExtras> {
+    let (template_routes, main) = template_project(template, vars)?;
+    let mut extras = InitExtras::default();
+    let mut history = BasicHistory::new().max_entries(20).no_duplicates(true);
+    loop {
+        let prompt = match extras.routes.is_empty() {
+            true => "Add a route?",
+            false => "Add another route?",
+        };
+        if !Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact()?
+        {
+            break;
+        }
+
+        let routes: Vec<_> = template_routes
+            .iter()
+            .chain(&extras.routes)
+            .cloned()
+            .collect();
+        let method = METHODS[FuzzySelect::new()
+            .with_prompt("Method")
+            .items(&METHODS)
+            .default(0)
+            .interact()?];
+        let completion = RouteCompletion::new(&routes);
+        let path: String = Input::new()
+            .with_prompt("Path")
+            .history_with(&mut history)
+            .completion_with(&completion)
+            .validate_with(|path: &String| {
+                validate_route_path(path)?;
+                match find_route(&routes, method, path) {
+                    Some(route) => Err(format!("is already routed to {}", route.handler)),
+                    None => Ok(()),
+                }
+            })
+            .interact_text()?;
+        let handler: String = Input::new()
+            .with_prompt("Handler")
+            .default(handler_name(method, &path))
+            .validate_with(|handler: &String| {
+                validate_handler(handler, &routes)?;
+                match declares(&main, handler) {
+                    true => Err(format!("is already declared in {ENTRY_FILE_NAME}")),
+                    false => Ok(()),
+                }
+            })
+            .interact_text()?;
+        extras.routes.push(RouteConfig {
+            path,
+            method: method.to_string(),
+            handler,
+        });
+    }
+
+    extras.tests = !template.contains("main.test.ts")
+        && Confirm::new()
+            .with_prompt("Add tests?")
+            .default(true)
+            .interact()?;
+    extras.tsconfig = !template.contains("tsconfig.json")
+        && Confirm::new()
+            .with_prompt("Add a tsconfig.json?")
+            .default(true)
+            .interact()?;
+    Ok(extras)
+}
+
+/// The routes of the template's config.yml and its main.ts, rendered without
+/// the wizard's routes, which must not collide with them.
+fn template_project(
+    template: &ProjectTemplate,
+    vars: &BTreeMap<String, String>,
+) -> Result<(Vec<RouteConfig>, String)> {
+    let mut routes = vec![];
+    let mut main = String::new();
+    for (file, content) in template.render(vars, &[])? {
+        if file == Path::new(CONFIG_FILE_NAME) {
+            routes = serde_yaml::from_slice::<ProjectConfig>(&content)
+                .map(|config| config.route)
+                .unwrap_or_default();
+        } else if file == Path::new(ENTRY_FILE_NAME) {
+            main = String::from_utf8_lossy(&content).into_owned();
+        }
+    }
+    Ok((routes, main))
+}
+
+/// Asks for the branch, remote and hook, and returns whether to commit.
+fn ask_git_setup(setup: &mut GitSetup) -> Result<bool> {
+    setup.branch = Input::new()
+        .with_prompt("Initial branch")
+        .default(setup.branch.clone())
+        .validate_with(|branch: &String| {
+            match git2::Reference::is_valid_name(&format!("refs/heads/{branch}")) {
+                true => Ok(()),
+                false => Err("is not a valid branch name"),
+            }
+        })
+        .interact_text()?;
+    let remote: String = Input::new()
+        .with_prompt("Git remote URL (empty for none)")
+        .allow_empty(true)
+        .interact_text()?;
+    setup.remote = Some(remote).filter(|remote| !remote.is_empty());
+    setup.hook = Confirm::new()
+        .with_prompt("Install a pre-commit hook checking the project?")
+        .default(setup.hook)
+        .interact()?;
+    Ok(Confirm::new()
+        .with_prompt("Create an initial commit?")
+        .default(true)
+        .interact()?)
+}
+
+/// Whether to create a repository at `path`: not when it already is one, and
+/// when it is inside another one, only if the user asks for a nested one.
+fn create_repo(path: &Path, interactive: bool) -> Result<bool> {
+    let Some(workdir) = enclosing_repo(path) else {
+        return Ok(true);
+    };
+    if same_path(&workdir, path) {
+        println!(
+            "{} {} is already a git repository",
+            "Note".yellow(),
+            path.display()
+        );
+        return Ok(false);
+    }
+
+    let prompt = format!(
+        "{} is inside the git repository {}, create a nested one?",
+        path.display(),
+        workdir.display()
+    );
+    if interactive
+        && Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact()?
+    {
+        return Ok(true);
+    }
+    println!(
+        "{} {} is inside the git repository {}, not creating one",
+        "Note".yellow(),
+        path.display(),
+        workdir.display()
+    );
+    Ok(false)
+}
+
+/// Finds the work tree of the repository containing `path`, which may not
+/// exist yet.
+fn enclosing_repo(path: &Path) -> Option<PathBuf> {
+    let path = env::current_dir().ok()?.join(path);
+    let existing = path.ancestors().find(|dir| dir.exists())?;
+    let repo = Repository::discover(existing).ok()?;
+    repo.workdir().map(Path::to_path_buf)
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The author from git config, checked before scaffolding anything.
+fn configured_author() -> Result<Signature<'static>> {
+    let config = Config::open_default()?;
+    let (Ok(name), Ok(email)) = (
+        config.get_string("user.name"),
+        config.get_string("user.email"),
+    ) else {
+        bail!("Set user.name and user.email in git config to create the initial commit");
+    };
+    Ok(Signature::now(&name, &email)?)
+}
+
+fn default_branch() -> Result<String> {
+    let config = Config::open_default()?;
+    Ok(config
+        .get_string("init.defaultBranch")
+        .unwrap_or_else(|_| DEFAULT_BRANCH.to_string()))
+}
+
+fn init_repo(path: &Path, setup: &GitSetup) -> Result<()> {
+    let repo = Repository::init_opts(
+        path,
+        RepositoryInitOptions::new().initial_head(&setup.branch),
+    )?;
+    if let Some(remote) = &setup.remote {
+        repo.remote("origin", remote)?;
+    }
+    if setup.hook {
+        install_hook(&repo)?;
+    }
+    if let Some(author) = &setup.author {
+        commit_all(&repo, author, "Initial commit")?;
+    }
+    Ok(())
+}
+
+fn install_hook(repo: &Repository) -> Result<()> {
+    let hooks = repo.path().join("hooks");
+    fs::create_dir_all(&hooks)?;
+    let hook = hooks.join("pre-commit");
+    fs::write(&hook, PRE_COMMIT_HOOK)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Commits every file of the work tree that isn't ignored.
+fn commit_all(repo: &Repository, signature: &Signature, message: &str) -> Result<()> {
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    repo.commit(Some("HEAD"), signature, signature, message, &tree, &[])?;
+    Ok(())
+}
+
+/// Completes route paths from the ones already added, e.g. `/api/users/`.
+struct RouteCompletion {
+    paths: Vec<String>,
+}
+
+impl RouteCompletion {
+    fn new(routes: &[RouteConfig]) -> Self {
+        let mut paths = vec!["/api/".to_string()];
+        for route in routes {
+            paths.push(route.path.clone());
+            paths.push(format!("{}/", route.path.trim_end_matches('/')));
+        }
+        Self { paths }
+    }
+}
+
+impl Completion for RouteCompletion {
+    fn get(&self, input: &str) -> Option<String> {
+        self.paths
+            .iter()
+            .find(|path| path.len() > input.len() && path.starts_with(input))
+            .cloned()
+    }
+}
+
+fn init_project(
+    path: &Path,
+    template: &ProjectTemplate,
+    vars: &BTreeMap<String, String>,
+    extras: &InitExtras,
+    git: Option<&GitSetup>,
+    force: bool,
+) -> Result<()> {
+    let mut files = template.render(vars, &extras.routes)?;
+    for (file, enabled) in [
+        ("main.test.ts", extras.tests),
+        ("tsconfig.json", extras.tsconfig),
+    ] {
+        if enabled {
+            files.extend(ProjectTemplate::extra(file)?.render(vars, &extras.routes)?);
+        }
+    }
+    check_target(path, force)?;
+
     if !path.exists() || !path.is_dir() {
         fs::create_dir_all(path)?;
     }
-    Repository::init(path)?;
+    for (file, content) in files {
+        let file = path.join(file);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, content)?;
+    }
+
+    if let Some(setup) = git {
+        init_repo(path, setup)?;
+    }
+    Ok(())
+}
+
+/// Names the project after its directory when not prompting: `dir`, or the
+/// current directory if it is empty, as the project would otherwise be
+/// created in a subdirectory named after it.
+fn default_name(dir: Option<&Path>, current_dir: &Path) -> Result<String> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None if is_empty_dir(current_dir)? => current_dir,
+        None => bail!(
+            "{} is not empty, pass a project name to create it in a subdirectory, or --dir",
+            current_dir.display()
+        ),
+    };
+    dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("Can't name the project after {}", dir.display()))
+}
 
-    fs::write(path.join("config.yml"), ConfigFile { name }.render()?)?;
-    fs::write(path.join("main.ts"), MainTsFile {}.render()?)?;
-    fs::write(path.join(".gitignore"), GitIgnoreFile {}.render()?)?;
+/// Fails before anything is asked or written when `path` has files in it.
+fn check_target(path: &Path, force: bool) -> Result<()> {
+    if !force && path.exists() && !is_empty_dir(path)? {
+        bail!(
+            "{} is not empty, use --force to scaffold into it anyway",
+            path.display()
+        );
+    }
+    Ok(())
+}
 
+/// Project names follow npm's package name rules, so that they can be used as
+/// one: lowercase, URL-safe and not starting with `.` or `_`.
+fn validate_project_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 214 {
+        return Err("must be between 1 and 214 characters long".to_string());
+    }
+    if name.contains(['/', '\\']) {
+        return Err("must not contain path separators".to_string());
+    }
+    if name.starts_with(['.', '_']) {
+        return Err("must not start with \".\" or \"_\"".to_string());
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(
+            "may only contain lowercase letters, digits, \"-\", \"_\" and \".\"".to_string(),
+        );
+    }
     Ok(())
 }
 
-#[derive(Template)]
-#[template(path = "../templates/config.yml.j2")]
-struct ConfigFile {
-    name: String,
+fn is_empty_dir(path: &Path) -> Result<bool> {
+    Ok(fs::read_dir(path)?.next().is_none())
 }
 
-#[derive(Template)]
-#[template(path = "../templates/main.ts.j2")]
-struct MainTsFile {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_project_name_should_follow_package_rules() {
+        for name in ["demo", "my-app", "app.v2", "app_1"] {
+            assert!(validate_project_name(name).is_ok(), "{name}");
+        }
+        for name in [
+            "", "My App", "../demo", "a/b", "a\\b", ".hidden", "_private", "Demo",
+        ] {
+            assert!(validate_project_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn default_name_should_need_an_empty_directory() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let current_dir = temp_dir.join("demo");
+        fs::create_dir(&current_dir)?;
+        assert_eq!(default_name(None, &current_dir)?, "demo");
+        assert_eq!(
+            default_name(Some(Path::new("other/app")), &current_dir)?,
+            "app"
+        );
+
+        fs::write(current_dir.join("notes.txt"), "")?;
+        assert!(default_name(None, &current_dir).is_err());
+        assert_eq!(
+            default_name(Some(Path::new("other/app")), &current_dir)?,
+            "app"
+        );
+        assert!(check_target(&current_dir, false).is_err());
+        check_target(&current_dir, true)?;
+        check_target(&current_dir.join("app"), false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn template_project_should_collect_routes_and_declarations() -> Result<()> {
+        let template = ProjectTemplate::load("api")?;
+        let vars = BTreeMap::from([
+            ("name".to_string(), "demo".to_string()),
+            ("resource".to_string(), "posts".to_string()),
+        ]);
+        let (routes, main) = template_project(&template, &vars)?;
+        assert!(find_route(&routes, "get", "/api/posts/{id}").is_some());
+        assert!(find_route(&routes, "DELETE", "/api/posts/{id}").is_none());
+        assert!(validate_handler("list", &routes).is_err());
+        for name in ["json", "posts"] {
+            assert!(declares(&main, name), "{name}");
+        }
+        assert!(!declares(&main, "getPosts"));
+        Ok(())
+    }
+
+    #[test]
+    fn init_project_should_scaffold_files() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let path = temp_dir.join("demo");
+        let template = ProjectTemplate::load(DEFAULT_TEMPLATE)?;
+        let vars = BTreeMap::from([("name".to_string(), "demo".to_string())]);
+
+        let extras = InitExtras::default();
+
+        init_project(&path, &template, &vars, &extras, None, false)?;
+        assert!(fs::read_to_string(path.join("config.yml"))?.contains("name: demo"));
+        assert!(path.join("main.ts").is_file());
+        assert!(!path.join(".git").exists());
+
+        // the directory isn't empty anymore
+        assert!(init_project(&path, &template, &vars, &extras, None, false).is_err());
+        init_project(&path, &template, &vars, &extras, Some(&git_setup()), true)?;
+        assert!(path.join(".git").is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn init_project_should_add_extras() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let template = ProjectTemplate::load(DEFAULT_TEMPLATE)?;
+        let vars = BTreeMap::from([("name".to_string(), "demo".to_string())]);
+        let extras = InitExtras {
+            routes: vec![RouteConfig {
+                path: "/api/users/{id}".to_string(),
+                method: "GET".to_string(),
+                handler: handler_name("GET", "/api/users/{id}"),
+            }],
+            tests: true,
+            tsconfig: true,
+        };
+        let git = GitSetup {
+            remote: Some("https://example.com/demo.git".to_string()),
+            ..git_setup()
+        };
+
+        init_project(&temp_dir, &template, &vars, &extras, Some(&git), false)?;
+        let config = fs::read_to_string(temp_dir.join("config.yml"))?;
+        assert!(config
+            .contains("  - path: /api/users/{id}\n    method: GET\n    handler: getUsersById\n"));
+        let main = fs::read_to_string(temp_dir.join("main.ts"))?;
+        assert!(main.contains("export { hello, getUsersById };"));
+        let tests = fs::read_to_string(temp_dir.join("main.test.ts"))?;
+        assert!(tests.contains("await request('/api/users/id', { method: 'GET' });"));
+        assert!(temp_dir.join("tsconfig.json").is_file());
+
+        let repo = Repository::open(&temp_dir)?;
+        assert_eq!(
+            repo.find_remote("origin")?.url(),
+            Some("https://example.com/demo.git")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn init_project_should_set_up_git() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let path = temp_dir.join("demo");
+        let template = ProjectTemplate::load(DEFAULT_TEMPLATE)?;
+        let vars = BTreeMap::from([("name".to_string(), "demo".to_string())]);
+        let git = GitSetup {
+            branch: "trunk".to_string(),
+            author: Some(Signature::now("Dino", "dino@example.com")?),
+            hook: true,
+            ..git_setup()
+        };
 
-#[derive(Template)]
-#[template(path = "../templates/.gitignore.j2")]
-struct GitIgnoreFile {}
+        init_project(
+            &path,
+            &template,
+            &vars,
+            &Default::default(),
+            Some(&git),
+            false,
+        )?;
+        let repo = Repository::open(&path)?;
+        let head = repo.head()?;
+        assert_eq!(head.shorthand(), Some("trunk"));
+        let commit = head.peel_to_commit()?;
+        assert_eq!(commit.author().name(), Some("Dino"));
+        assert_eq!(commit.message(), Some("Initial commit"));
+        assert!(commit.tree()?.get_name("config.yml").is_some());
+        assert!(repo.statuses(None)?.is_empty());
+        let hook = fs::read_to_string(repo.path().join("hooks/pre-commit"))?;
+        assert!(hook.contains("dino build"));
+
+        // projects inside the repository don't get their own by default
+        assert!(same_path(&enclosing_repo(&path).unwrap(), &path));
+        let nested = path.join("nested");
+        assert!(same_path(&enclosing_repo(&nested).unwrap(), &path));
+        assert!(!create_repo(&nested, false)?);
+        assert!(!create_repo(&path, false)?);
+        Ok(())
+    }
+
+    fn git_setup() -> GitSetup {
+        GitSetup {
+            branch: DEFAULT_BRANCH.to_string(),
+            remote: None,
+            author: None,
+            hook: false,
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{validate_profile_name, OutputFormat};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// `build/manifest.json`: the builds of each profile, newest first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub profiles: BTreeMap<String, Vec<BuildRecord>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub hash: String,
+    pub profile: String,
+    /// Paths relative to the build directory.
+    pub bundle: String,
+    pub source_map: Option<String>,
+    /// Module format of the bundle, which decides how it is loaded.
+    #[serde(default)]
+    pub format: OutputFormat,
+    pub assets: Vec<String>,
+    /// Legal comments extracted from the bundle.
+    #[serde(default)]
+    pub licenses: Option<String>,
+    /// Modules in the graph, with the hash of their contents.
+    pub inputs: BTreeMap<String, String>,
+    pub dino_version: String,
+    /// Seconds since the Unix epoch.
+    pub built_at: u64,
+}
+
+impl Manifest {
+    pub fn load(build_path: &Path) -> Result<Self> {
+        let file = build_path.join(MANIFEST_FILE_NAME);
+        if !file.is_file() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&file)?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid {}, run `dino clean` to start over", file.display()))
+    }
+
+    pub fn save(&self, build_path: &Path) -> Result<()> {
+        // write then rename, so that a concurrent `load` never sees half a file
+        let tmp = build_path.join(format!(
+            ".{MANIFEST_FILE_NAME}.{}.{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, build_path.join(MANIFEST_FILE_NAME))?;
+        Ok(())
+    }
+
+    /// The latest build of a profile.
+    pub fn current(&self, profile: &str) -> Option<&BuildRecord> {
+        self.profiles.get(profile).and_then(|builds| builds.first())
+    }
+
+    /// Makes `record` the current build of its profile, and deletes the files
+    /// of builds beyond the `keep` most recent ones.
+    pub fn push(&mut self, build_path: &Path, record: BuildRecord, keep: usize) -> Result<()> {
+        let builds = self.profiles.entry(record.profile.clone()).or_default();
+        builds.retain(|build| build.hash != record.hash);
+        builds.insert(0, record);
+
+        let stale = builds.split_off(keep.max(1).min(builds.len()));
+        let kept: BTreeSet<&String> = builds.iter().flat_map(BuildRecord::files).collect();
+        for file in stale.iter().flat_map(BuildRecord::files) {
+            if !kept.contains(file) {
+                remove_file(&build_path.join(file))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets a profile's builds and deletes its directory.
+    pub fn remove_profile(&mut self, build_path: &Path, profile: &str) -> Result<()> {
+        validate_profile_name(profile)?;
+        self.profiles.remove(profile);
+        let profile_path = build_path.join(profile);
+        if profile_path.is_dir() {
+            fs::remove_dir_all(profile_path)?;
+        }
+        Ok(())
+    }
+}
+
+impl BuildRecord {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    fn files(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.bundle)
+            .chain(&self.source_map)
+            .chain(&self.assets)
+            .chain(&self.licenses)
+    }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hash: &str, assets: &[&str]) -> BuildRecord {
+        BuildRecord {
+            hash: hash.to_string(),
+            profile: "dev".to_string(),
+            bundle: format!("dev/{hash}.js"),
+            source_map: Some(format!("dev/{hash}.js.map")),
+            format: OutputFormat::Iife,
+            assets: assets.iter().map(|a| format!("dev/{a}")).collect(),
+            licenses: None,
+            inputs: BTreeMap::from([("main.ts".to_string(), hash.to_string())]),
+            dino_version: env!("CARGO_PKG_VERSION").to_string(),
+            built_at: BuildRecord::now(),
+        }
+    }
+
+    #[test]
+    fn manifest_should_prune_stale_builds() -> Result<()> {
+        let build_path = assert_fs::TempDir::new()?;
+        fs::create_dir_all(build_path.join("dev"))?;
+        for file in ["a.js", "a.js.map", "b.js", "b.js.map", "c.js", "c.js.map"] {
+            fs::write(build_path.join("dev").join(file), "")?;
+        }
+        for file in ["old.png", "shared.png"] {
+            fs::write(build_path.join("dev").join(file), "")?;
+        }
+
+        let mut manifest = Manifest::default();
+        manifest.push(&build_path, record("a", &["old.png", "shared.png"]), 2)?;
+        manifest.push(&build_path, record("b", &["shared.png"]), 2)?;
+        manifest.push(&build_path, record("c", &["shared.png"]), 2)?;
+        manifest.save(&build_path)?;
+
+        let manifest = Manifest::load(&build_path)?;
+        assert_eq!(manifest.current("dev").unwrap().hash, "c");
+        assert_eq!(manifest.profiles["dev"].len(), 2);
+        assert!(!build_path.join("dev/a.js").exists());
+        assert!(!build_path.join("dev/a.js.map").exists());
+        assert!(!build_path.join("dev/old.png").exists());
+        assert!(build_path.join("dev/b.js").exists());
+        assert!(build_path.join("dev/shared.png").exists());
+        Ok(())
+    }
+}
@@ -1,24 +1,42 @@
+mod add_opts;
 mod build_opts;
+mod clean_opts;
+mod coverage;
 mod init_opts;
+mod manifest;
+mod repl_opts;
+mod routes;
 mod run_opts;
+mod template;
+mod test_opts;
+mod test_report;
 
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 
+use add_opts::AddOpts;
 use build_opts::BuildOpts;
+use clean_opts::CleanOpts;
 use init_opts::InitOpts;
+use manifest::{BuildRecord, Manifest};
+use repl_opts::ReplOpts;
 use run_opts::RunOpts;
+use test_opts::TestOpts;
 
-use std::{
-    collections::BTreeSet,
-    fs::{self, File},
-    path::{Path, PathBuf},
-};
+use std::{collections::BTreeMap, fs, path::Path};
 
 use anyhow::Result;
-use glob::glob;
+use chrono::{SecondsFormat, Utc};
+use git2::Repository;
+
+use colored::Colorize;
+use serde::Serialize;
+use swc_ecma_ast::EsVersion;
 
-use crate::run_bundle;
+use crate::{
+    bundle, import_hash, BuildConfig, BuildMetadata, LegalComments, MinifierOptions, MinifyConfig,
+    Options, OutputFormat, ProjectConfig,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "dino", version, author, about, long_about = None)]
@@ -36,6 +54,14 @@ pub enum SubCommand {
     Build(BuildOpts),
     #[command(name = "run", about = "Run user's dino project")]
     Run(RunOpts),
+    #[command(name = "clean", about = "Remove build artifacts")]
+    Clean(CleanOpts),
+    #[command(name = "add", about = "Add a route to dino project")]
+    Add(AddOpts),
+    #[command(name = "test", about = "Run the tests of dino project")]
+    Test(TestOpts),
+    #[command(name = "repl", about = "Evaluate TypeScript interactively")]
+    Repl(ReplOpts),
 }
 
 #[allow(async_fn_in_trait)]
@@ -46,60 +72,380 @@ pub trait CmdExector {
 
 const BUILD_DIR_NAME: &str = "build";
 const ENTRY_FILE_NAME: &str = "main.ts";
-const EXTS: [&str; 3] = ["ts", "js", "json"];
+const CACHE_DIR_NAME: &str = ".cache";
+/// Builds kept per profile when `build.keep_builds` is not set.
+const DEFAULT_KEEP_BUILDS: usize = 3;
+const ANALYZE_JSON_FILE_NAME: &str = "analyze.json";
+const ANALYZE_HTML_FILE_NAME: &str = "analyze.html";
+const LICENSES_FILE_NAME: &str = "LICENSES.txt";
+
+/// Options for bundling the project's sources to run them right away rather
+/// than build them, e.g. for tests: an ES module with the profile's defines,
+/// polyfills and target. The sources count as built when they are bundled.
+fn source_options(path: &Path, project: &ProjectConfig, config: &BuildConfig) -> Result<Options> {
+    let mut options = Options {
+        format: OutputFormat::Esm,
+        define: config.resolve_define(path)?,
+        polyfills: config.polyfills.clone().unwrap_or_default(),
+        metadata: Some(BuildMetadata {
+            built_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ..build_metadata(path, project)
+        }),
+        ..Default::default()
+    };
+    if let Some(target) = config.target {
+        options.target = target;
+    }
+    Ok(options)
+}
+
+fn build_project(path: &Path, profile: &str, overrides: &BuildConfig) -> Result<String> {
+    let project = ProjectConfig::load(path)?;
+    let config = project.profile(profile)?.merge(overrides);
 
-fn build_project(path: &Path) -> Result<String> {
     let build_path = path.join(BUILD_DIR_NAME);
-    if !build_path.exists() || !build_path.is_dir() {
-        fs::create_dir_all(&build_path)?;
+    let profile_path = build_path.join(profile);
+    if !profile_path.exists() || !profile_path.is_dir() {
+        fs::create_dir_all(&profile_path)?;
+    }
+    let minify = config
+        .minify
+        .clone()
+        .unwrap_or(MinifyConfig::Enabled(false));
+    let mut options = Options {
+        minify: minify.strip_whitespace(),
+        minifier: minify.minifier(),
+        asset_dir: Some(profile_path.clone()),
+        cache_dir: Some(build_path.join(CACHE_DIR_NAME)),
+        define: config.resolve_define(path)?,
+        analyze: config.analyze,
+        source_map: config.source_map.unwrap_or_default(),
+        format: config.format.unwrap_or_default(),
+        polyfills: config.polyfills.unwrap_or_default(),
+        legal_comments: config.legal_comments.unwrap_or_default(),
+        metadata: Some(build_metadata(path, &project)),
+        banner: config.banner,
+        footer: config.footer,
+        ..Default::default()
+    };
+    if let Some(target) = config.target {
+        options.target = target;
     }
 
     let main_ts = path.join(ENTRY_FILE_NAME);
-    let build_file_name = generate_build_file_name(path, &build_path)?;
-    let build_file = build_path.join(build_file_name);
+    let keep = config.keep_builds.unwrap_or(DEFAULT_KEEP_BUILDS);
+    let fingerprint = build_fingerprint(profile, &options)?;
+    // a reused build keeps the time it was built at
+    if let Some(metadata) = &mut options.metadata {
+        metadata.built_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    }
 
-    // if the file already exists, skip building
-    if build_file.exists() {
+    // if a build of the same module graph exists, make it current and skip
+    // building, unless a report is wanted
+    let mut manifest = Manifest::load(&build_path)?;
+    let existing = manifest
+        .profiles
+        .get(profile)
+        .and_then(|builds| {
+            builds
+                .iter()
+                .find(|build| is_up_to_date(build, &build_path, &fingerprint))
+        })
+        .cloned();
+    if let Some(record) = existing.filter(|_| !options.analyze) {
+        let build_file = build_path.join(&record.bundle);
+        manifest.push(&build_path, record, keep)?;
+        manifest.save(&build_path)?;
         return Ok(build_file.display().to_string());
     }
 
-    fs::write(
-        &build_file,
-        run_bundle(&main_ts.display().to_string(), &Default::default())?,
-    )?;
+    let output = bundle(&main_ts.display().to_string(), &options)?;
+    if let Some(unminified_size) = output.unminified_size {
+        println!(
+            "{} {} -> {} bytes",
+            "Minified".green(),
+            unminified_size,
+            output.code.len()
+        );
+    }
+    if let Some(analysis) = output.analysis {
+        fs::write(
+            profile_path.join(ANALYZE_JSON_FILE_NAME),
+            analysis.to_json()?,
+        )?;
+        fs::write(
+            profile_path.join(ANALYZE_HTML_FILE_NAME),
+            analysis.to_html()?,
+        )?;
+        println!(
+            "{} {}",
+            "Analysis".green(),
+            profile_path.join(ANALYZE_HTML_FILE_NAME).display()
+        );
+    }
+
+    let hash = generate_build_hash(&fingerprint, &output.inputs);
+    let build_file_name = format!("{hash}.js");
+    let build_file = profile_path.join(&build_file_name);
+
+    let mut code = output.code;
+    let mut source_map_file = None;
+    if let Some(source_map) = output.source_map {
+        let map_file_name = format!("{build_file_name}.map");
+        fs::write(profile_path.join(&map_file_name), source_map)?;
+        code.push_str(&format!("\n//# sourceMappingURL={map_file_name}\n"));
+        source_map_file = Some(format!("{profile}/{map_file_name}"));
+    }
+    fs::write(&build_file, code)?;
+
+    let mut licenses_file = None;
+    if options.legal_comments == LegalComments::External && !output.licenses.is_empty() {
+        let licenses_file_name = format!("{hash}.{LICENSES_FILE_NAME}");
+        let mut licenses = output.licenses.join("\n\n");
+        licenses.push('\n');
+        fs::write(profile_path.join(&licenses_file_name), licenses)?;
+        licenses_file = Some(format!("{profile}/{licenses_file_name}"));
+    }
+
+    let record = BuildRecord {
+        hash,
+        profile: profile.to_string(),
+        bundle: format!("{profile}/{build_file_name}"),
+        source_map: source_map_file,
+        format: options.format,
+        assets: output
+            .assets
+            .iter()
+            .map(|asset| format!("{profile}/{asset}"))
+            .collect(),
+        licenses: licenses_file,
+        inputs: output.inputs,
+        dino_version: env!("CARGO_PKG_VERSION").to_string(),
+        built_at: BuildRecord::now(),
+    };
+    manifest.push(&build_path, record, keep)?;
+    manifest.save(&build_path)?;
+
     Ok(build_file.display().to_string())
 }
 
-fn generate_build_file_name(path: &Path, build_path: &Path) -> Result<String> {
-    let mut files: BTreeSet<PathBuf> = BTreeSet::new();
-    for ext in EXTS {
-        let tmps: BTreeSet<_> = glob(&format!("{}/**/*.{ext}", path.display()))?
-            .filter_map(|f| f.ok())
-            .filter(|f| !f.starts_with(build_path.display().to_string()))
-            .collect();
-        files.extend(tmps);
+/// Identifies the project checked out at `path`, leaving `built_at` to the
+/// caller.
+fn build_metadata(path: &Path, project: &ProjectConfig) -> BuildMetadata {
+    BuildMetadata {
+        name: project.name.clone(),
+        version: project.version.clone(),
+        commit: git_commit(path),
+        built_at: String::new(),
     }
+}
+
+/// Abbreviated hash of the commit checked out in the project's repository.
+fn git_commit(path: &Path) -> Option<String> {
+    let repo = Repository::discover(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string()[..7].to_string())
+}
+
+/// Everything besides the module graph that goes into a build, i.e. the
+/// options that change the output.
+#[derive(Serialize)]
+struct BuildFingerprint<'a> {
+    dino_version: &'a str,
+    profile: &'a str,
+    minify: bool,
+    minifier: &'a Option<MinifierOptions>,
+    define: &'a BTreeMap<String, String>,
+    source_map: bool,
+    format: OutputFormat,
+    target: EsVersion,
+    legal_comments: LegalComments,
+    polyfills: &'a [String],
+    banner: &'a Option<String>,
+    footer: &'a Option<String>,
+    name: Option<&'a str>,
+    version: Option<&'a str>,
+    commit: Option<&'a str>,
+}
+
+/// Serializes the fingerprint of a build with `options`.
+fn build_fingerprint(profile: &str, options: &Options) -> Result<String> {
+    let metadata = options.metadata.as_ref();
+    let fingerprint = BuildFingerprint {
+        dino_version: env!("CARGO_PKG_VERSION"),
+        profile,
+        minify: options.minify,
+        minifier: &options.minifier,
+        define: &options.define,
+        source_map: options.source_map,
+        format: options.format,
+        target: options.target,
+        legal_comments: options.legal_comments,
+        polyfills: &options.polyfills,
+        banner: &options.banner,
+        footer: &options.footer,
+        name: metadata.map(|metadata| metadata.name.as_str()),
+        version: metadata.and_then(|metadata| metadata.version.as_deref()),
+        commit: metadata.and_then(|metadata| metadata.commit.as_deref()),
+    };
+    Ok(serde_json::to_string(&fingerprint)?)
+}
+
+/// Hashes the build settings along with the path and contents of every
+/// module in the graph.
+fn generate_build_hash(fingerprint: &str, inputs: &BTreeMap<String, String>) -> String {
     let mut hasher = blake3::Hasher::new();
-    for file in files {
-        hasher.update_reader(File::open(file)?)?;
+    hasher.update(fingerprint.as_bytes());
+    for (specifier, hash) in inputs {
+        hasher.update(format!("\n{specifier}\0{hash}").as_bytes());
     }
-    let hash = format!("{}.js", hasher.finalize().to_string());
-    Ok(hash)
+    hasher.finalize().to_string()
+}
+
+/// Whether a previous build has the same settings and none of its modules
+/// changed since.
+fn is_up_to_date(build: &BuildRecord, build_path: &Path, fingerprint: &str) -> bool {
+    let inputs: Option<BTreeMap<String, String>> = build
+        .inputs
+        .keys()
+        .map(|specifier| Some((specifier.clone(), import_hash(specifier).ok()?)))
+        .collect();
+
+    inputs.is_some_and(|inputs| generate_build_hash(fingerprint, &inputs) == build.hash)
+        && build_path.join(&build.bundle).is_file()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::env;
+    use std::{env, fs, path::Path};
 
     use anyhow::Result;
 
-    use super::build_project;
+    use super::{build_project, Manifest, BUILD_DIR_NAME};
+    use crate::{BuildConfig, LegalComments, DEFAULT_PROFILE};
 
     #[test]
     fn build_project_should_work() -> Result<()> {
         let demo_path = env::current_dir()?.join("demo");
-        let build = build_project(&demo_path)?;
+        let build = build_project(&demo_path, DEFAULT_PROFILE, &Default::default())?;
         println!("{build}");
+        assert!(build.contains("/build/dev/"));
+        assert!(Path::new(&format!("{build}.map")).is_file());
+
+        let build = build_project(&demo_path, "release", &Default::default())?;
+        assert!(build.contains("/build/release/"));
+        assert!(!Path::new(&format!("{build}.map")).exists());
+
+        let manifest = Manifest::load(&demo_path.join(BUILD_DIR_NAME))?;
+        let current = manifest.current("release").unwrap();
+        assert!(build.ends_with(&current.bundle));
+        assert!(current
+            .inputs
+            .contains_key(&demo_path.join("main.ts").display().to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn build_project_should_check_the_profile_first() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let path = temp_dir.join("demo");
+        fs::create_dir(&path)?;
+        fs::write(path.join("main.ts"), "export function hello() {}\n")?;
+
+        for profile in ["relase", "../x", ".cache"] {
+            assert!(build_project(&path, profile, &Default::default()).is_err());
+        }
+        assert!(!path.join(BUILD_DIR_NAME).exists());
+        assert!(!temp_dir.join("x").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn build_project_should_key_builds_on_module_graph() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            "import { name } from './lib.ts';\nexport function hello() { return name; }\n",
+        )?;
+        fs::write(temp_dir.join("lib.ts"), "export const name = 'a';\n")?;
+        fs::write(temp_dir.join("script.ts"), "console.log(1);\n")?;
+
+        let first = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+
+        // files outside of the module graph don't matter
+        fs::write(temp_dir.join("script.ts"), "console.log(2);\n")?;
+        let second = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        assert_eq!(first, second);
+
+        fs::write(temp_dir.join("lib.ts"), "export const name = 'b';\n")?;
+        let third = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        assert_ne!(first, third);
+
+        // reverting picks the earlier build back up
+        fs::write(temp_dir.join("lib.ts"), "export const name = 'a';\n")?;
+        let fourth = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        assert_eq!(first, fourth);
+        let manifest = Manifest::load(&temp_dir.join(BUILD_DIR_NAME))?;
+        assert!(fourth.ends_with(&manifest.current(DEFAULT_PROFILE).unwrap().bundle));
+        Ok(())
+    }
+
+    #[test]
+    fn build_project_should_key_builds_on_extensionless_imports() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            "import { name } from './lib';\nimport { id } from './util';\n\
+             export function hello() { return name + id; }\n",
+        )?;
+        fs::write(temp_dir.join("lib.js"), "export const name = 'a';\n")?;
+        fs::create_dir(temp_dir.join("util"))?;
+        fs::write(temp_dir.join("util/index.js"), "export const id = 1;\n")?;
+
+        let first = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        fs::write(temp_dir.join("lib.js"), "export const name = 'b';\n")?;
+        let second = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        assert_ne!(first, second);
+        assert!(fs::read_to_string(&second)?.contains("'b'"));
+
+        fs::write(temp_dir.join("util/index.js"), "export const id = 2;\n")?;
+        let third = build_project(&temp_dir, DEFAULT_PROFILE, &Default::default())?;
+        assert_ne!(second, third);
+        Ok(())
+    }
+
+    #[test]
+    fn build_project_should_extract_licenses() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            "import { name } from './lib.ts';\nexport function hello() { return name; }\n",
+        )?;
+        fs::write(
+            temp_dir.join("lib.ts"),
+            "/*! lib v1 | MIT */\nexport const name: string = 'a';\n",
+        )?;
+        let overrides = BuildConfig {
+            legal_comments: Some(LegalComments::External),
+            ..Default::default()
+        };
+
+        let build = build_project(&temp_dir, DEFAULT_PROFILE, &overrides)?;
+        assert!(!fs::read_to_string(&build)?.contains("MIT"));
+
+        // lib.ts now comes from the transpile cache
+        fs::write(
+            temp_dir.join("main.ts"),
+            "import { name } from './lib.ts';\nexport function hi() { return name; }\n",
+        )?;
+        build_project(&temp_dir, DEFAULT_PROFILE, &overrides)?;
+        let build_path = temp_dir.join(BUILD_DIR_NAME);
+        let manifest = Manifest::load(&build_path)?;
+        let licenses = manifest.current(DEFAULT_PROFILE).unwrap().licenses.as_ref();
+        assert_eq!(
+            fs::read_to_string(build_path.join(licenses.unwrap()))?,
+            "/*! lib v1 | MIT */\n"
+        );
         Ok(())
     }
 }
@@ -0,0 +1,302 @@
+use std::{
+    collections::VecDeque,
+    env, fs,
+    io::{self, BufRead, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+use dialoguer::{Completion, History, Input};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::{source_options, CmdExector, ENTRY_FILE_NAME};
+use crate::{bundle, compile_script, JsWorker, Options, ProjectConfig, DEFAULT_PROFILE};
+
+/// Inputs kept in the history file.
+const MAX_HISTORY: usize = 1000;
+const HISTORY_FILE_NAME: &str = "repl_history";
+
+lazy_static! {
+    // The member chain being typed, e.g. `console.lo`.
+    static ref COMPLETION_REGEX: Regex =
+        Regex::new(r"((?:[A-Za-z_$][\w$]*\.)*)([A-Za-z_$][\w$]*)?$").unwrap();
+}
+
+#[derive(Debug, Parser)]
+pub struct ReplOpts {}
+
+impl CmdExector for ReplOpts {
+    async fn execute(self) -> Result<()> {
+        let path = env::current_dir()?;
+        let repl = Repl::new(&path)?;
+        match io::stdin().is_terminal() {
+            true => repl.interact(),
+            // piped input is evaluated line by line
+            false => {
+                for line in io::stdin().lock().lines() {
+                    repl.print(&line?);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Evaluates TypeScript in a worker set up like the project's, with the
+/// handlers of main.ts and its routes.
+struct Repl {
+    path: PathBuf,
+    options: Options,
+    worker: JsWorker,
+}
+
+impl Repl {
+    fn new(path: &Path) -> Result<Self> {
+        let project = ProjectConfig::load(path)?;
+        let options = source_options(path, &project, &project.profile(DEFAULT_PROFILE)?)?;
+        let entry = path.join(ENTRY_FILE_NAME);
+        let handlers = match entry.exists() {
+            true => {
+                bundle(&entry.display().to_string(), &options)
+                    .with_context(|| format!("Failed to bundle {ENTRY_FILE_NAME}"))?
+                    .code
+            }
+            false => String::new(),
+        };
+        let worker = JsWorker::try_new_with_format(&handlers, options.format)?;
+        worker.set_routes(&project.route)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            options,
+            worker,
+        })
+    }
+
+    fn interact(&self) -> Result<()> {
+        println!(
+            "Dino v{} REPL, type {} to leave",
+            env!("CARGO_PKG_VERSION"),
+            ".exit".bold()
+        );
+        let mut history =
+            FileHistory::load(dirs::data_dir().map(|dir| dir.join("dino").join(HISTORY_FILE_NAME)));
+        let completion = PropertyCompletion {
+            worker: &self.worker,
+        };
+        loop {
+            // Ctrl+C and Ctrl+D end the input
+            let Ok(input) = Input::<String>::new()
+                .with_prompt(">")
+                .allow_empty(true)
+                .history_with(&mut history)
+                .completion_with(&completion)
+                .interact_text()
+            else {
+                return Ok(());
+            };
+            match input.trim() {
+                "" => {}
+                ".exit" => return Ok(()),
+                input => self.print(input),
+            }
+        }
+    }
+
+    /// Evaluates the input and prints its value or error.
+    fn print(&self, input: &str) {
+        match self.eval(input) {
+            Ok(Some(value)) => println!("{value}"),
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", format!("{e:#}").red()),
+        }
+    }
+
+    /// Compiles and runs the input, bundling the project modules it imports
+    /// first, so that edits to them show up when imported again.
+    fn eval(&self, input: &str) -> Result<Option<String>> {
+        let script = compile_script(input)?;
+        for specifier in &script.imports {
+            let module = match specifier.starts_with('.') {
+                true => self.path.join(specifier).display().to_string(),
+                false => specifier.clone(),
+            };
+            let output = bundle(&module, &self.options)
+                .with_context(|| format!("Failed to import \"{specifier}\""))?;
+            self.worker
+                .load(specifier, &output.code, self.options.format)?;
+        }
+        self.worker.eval(&script.code)
+    }
+}
+
+/// History kept in a file across sessions, most recent last.
+struct FileHistory {
+    file: Option<PathBuf>,
+    entries: VecDeque<String>,
+}
+
+impl FileHistory {
+    fn load(file: Option<PathBuf>) -> Self {
+        let mut entries: VecDeque<String> = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect();
+        while entries.len() > MAX_HISTORY {
+            entries.pop_front();
+        }
+        Self { file, entries }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut content = Vec::from(self.entries.clone()).join("\n");
+        content.push('\n');
+        fs::write(file, content)?;
+        Ok(())
+    }
+}
+
+impl History<String> for FileHistory {
+    fn read(&self, pos: usize) -> Option<String> {
+        self.entries.iter().rev().nth(pos).cloned()
+    }
+
+    fn write(&mut self, val: &String) {
+        let val = val.trim();
+        if val.is_empty() || self.entries.back().is_some_and(|last| last == val) {
+            return;
+        }
+        self.entries.push_back(val.to_string());
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.pop_front();
+        }
+        // the REPL works without history
+        let _ = self.save();
+    }
+}
+
+/// Completes the names of globals and of the properties of objects, e.g.
+/// `console.lo` to `console.log`.
+struct PropertyCompletion<'a> {
+    worker: &'a JsWorker,
+}
+
+impl Completion for PropertyCompletion<'_> {
+    fn get(&self, input: &str) -> Option<String> {
+        let captures = COMPLETION_REGEX.captures(input)?;
+        let start = captures.get(0)?.start();
+        // e.g. a call's result, which completing would run
+        if input[..start].ends_with(['.', ')', ']']) {
+            return None;
+        }
+        let object = captures[1].trim_end_matches('.');
+        let prefix = captures.get(2).map_or("", |m| m.as_str());
+        if object.is_empty() && prefix.is_empty() {
+            return None;
+        }
+
+        let mut names: Vec<_> = self
+            .worker
+            .property_names(match object {
+                "" => "globalThis",
+                object => object,
+            })
+            .into_iter()
+            .filter(|name| name.len() > prefix.len() && name.starts_with(prefix))
+            .collect();
+        names.sort();
+        let completed = match names.as_slice() {
+            [] => return None,
+            [name] => name.clone(),
+            [first, rest @ ..] => {
+                // as far as the names agree, or the first one
+                let common = rest.iter().fold(first.as_str(), |common, name| {
+                    let len = common
+                        .chars()
+                        .zip(name.chars())
+                        .take_while(|(a, b)| a == b)
+                        .map(|(a, _)| a.len_utf8())
+                        .sum();
+                    &common[..len]
+                });
+                match common.len() > prefix.len() {
+                    true => common.to_string(),
+                    false => first.clone(),
+                }
+            }
+        };
+        Some(format!(
+            "{}{completed}",
+            &input[..input.len() - prefix.len()]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repl_should_eval_typescript() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("lib.ts"),
+            "export const double = (n: number): number => n * 2;\n",
+        )?;
+        let repl = Repl::new(&temp_dir)?;
+
+        let eval = |input: &str| repl.eval(input).map(|value| value.unwrap_or_default());
+        assert_eq!(eval("import { double } from './lib.ts';")?, "");
+        assert_eq!(eval("const n: number = await Promise.resolve(21);")?, "");
+        assert_eq!(eval("double(n)")?, "42");
+        // input can be run again
+        assert_eq!(eval("const n = 2; class Point {}")?, "");
+        assert_eq!(eval("const n = 3; class Point {}")?, "");
+        assert_eq!(eval("double(n)")?, "6");
+        assert_eq!(eval("let m = n; m")?, "3");
+        assert_eq!(eval("let m = 4; m")?, "4");
+        assert_eq!(
+            eval("import assert from 'assert'; typeof assert")?,
+            "\"function\""
+        );
+        assert!(repl.eval("import './missing.ts';").is_err());
+
+        let completion = PropertyCompletion {
+            worker: &repl.worker,
+        };
+        assert_eq!(completion.get("console.lo").as_deref(), Some("console.log"));
+        assert_eq!(
+            completion.get("x = setTime").as_deref(),
+            Some("x = setTimeout")
+        );
+        assert_eq!(completion.get("double(1).to"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn file_history_should_persist_inputs() {
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        let file = temp_dir.join("dino/repl_history");
+        let mut history = FileHistory::load(Some(file.clone()));
+        history.write(&"1 + 1".to_string());
+        history.write(&"1 + 1".to_string());
+        history.write(&"  ".to_string());
+        history.write(&"Math.PI".to_string());
+
+        let history = FileHistory::load(Some(file));
+        assert_eq!(history.read(0).as_deref(), Some("Math.PI"));
+        assert_eq!(history.read(1).as_deref(), Some("1 + 1"));
+        assert_eq!(history.read(2), None);
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::ENTRY_FILE_NAME;
+use crate::{ProjectConfig, RouteConfig, CONFIG_FILE_NAME};
+
+pub const METHODS: [&str; 5] = ["GET", "POST", "PUT", "PATCH", "DELETE"];
+/// Directory of handlers generated in their own file.
+const HANDLERS_DIR_NAME: &str = "handlers";
+/// Declares `Req` and `Res` for generated handlers.
+const TYPES_FILE_NAME: &str = "types.ts";
+pub const TYPES_FILE: &str = "/** A request, as passed to handlers. */
+export interface Req {
+  method: string;
+  url: string;
+  header: Record<string, string>;
+  /** Values of the route's `{name}` path segments. */
+  params: Record<string, string>;
+  body?: string;
+}
+
+/** A response, as returned by handlers. */
+export interface Res {
+  status: number;
+  headers: Record<string, string>;
+  body?: string;
+}
+";
+
+lazy_static! {
+    /// The last `export { ... };` list that doesn't re-export another module.
+    static ref EXPORT_LIST_REGEX: Regex =
+        Regex::new(r"(?m)^export\s*\{([^}]*)\}\s*;?[ \t]*$").unwrap();
+    /// A top-level `route:` key, possibly with an empty flow sequence.
+    static ref ROUTE_KEY_REGEX: Regex = Regex::new(r"^route:\s*(\[\s*\])?\s*(#.*)?$").unwrap();
+    /// Declarations of `Req` in a module.
+    static ref REQ_TYPE_REGEX: Regex = Regex::new(r"\b(interface|type)\s+Req\b|\bReq\b[^;]*\bfrom\b").unwrap();
+}
+
+pub fn validate_route_path(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err("must start with \"/\"".to_string());
+    }
+    if path.contains(char::is_whitespace) {
+        return Err("must not contain whitespace".to_string());
+    }
+    // the starters put it in quoted strings as is
+    if path.contains(['\'', '"', '\\']) {
+        return Err("must not contain quotes or backslashes".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_handler(handler: &str, routes: &[RouteConfig]) -> Result<(), String> {
+    let mut chars = handler.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '_' | '$'))
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$'));
+    if !valid {
+        return Err("must be a JavaScript identifier".to_string());
+    }
+    if routes.iter().any(|route| route.handler == handler) {
+        return Err("is already used by another route".to_string());
+    }
+    Ok(())
+}
+
+/// The route already taking requests for `method` and `path`, if any.
+pub fn find_route<'a>(
+    routes: &'a [RouteConfig],
+    method: &str,
+    path: &str,
+) -> Option<&'a RouteConfig> {
+    routes
+        .iter()
+        .find(|route| route.method.eq_ignore_ascii_case(method) && route.path == path)
+}
+
+/// Whether a module declares `name` as a function, class or variable.
+pub fn declares(module: &str, name: &str) -> bool {
+    Regex::new(&format!(
+        r"\b(function|const|let|var|class)\s+{}\b",
+        regex::escape(name)
+    ))
+    .is_ok_and(|declaration| declaration.is_match(module))
+}
+
+/// Suggests a handler name for a route, e.g. `getUsersById` for
+/// `GET /api/users/{id}`.
+pub fn handler_name(method: &str, path: &str) -> String {
+    let mut name = method.to_lowercase();
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != "api") {
+        let (prefix, segment) = match segment
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .or_else(|| segment.strip_prefix(':'))
+        {
+            Some(param) => ("By", param),
+            None => ("", segment),
+        };
+        name.push_str(prefix);
+        for word in segment
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+        {
+            let mut chars = word.chars();
+            name.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+            name.push_str(chars.as_str());
+        }
+    }
+    if name == method.to_lowercase() {
+        name.push_str("Index");
+    }
+    name
+}
+
+/// Adds a route to the project's config.yml, and a handler stub exported from
+/// main.ts, either written into it or into its own file under `handlers/`.
+/// Returns the files written.
+pub fn add_route(project: &Path, route: &RouteConfig, own_file: bool) -> Result<Vec<PathBuf>> {
+    let config_file = project.join(CONFIG_FILE_NAME);
+    let entry_file = project.join(ENTRY_FILE_NAME);
+    if !config_file.is_file() || !entry_file.is_file() {
+        bail!(
+            "{} is not a dino project, {CONFIG_FILE_NAME} or {ENTRY_FILE_NAME} is missing",
+            project.display()
+        );
+    }
+
+    let routes = ProjectConfig::load(project)?.route;
+    if let Some(existing) = find_route(&routes, &route.method, &route.path) {
+        bail!(
+            "{} {} is already routed to {}",
+            route.method,
+            route.path,
+            existing.handler
+        );
+    }
+    validate_handler(&route.handler, &routes)
+        .map_err(|e| anyhow::anyhow!("Handler \"{}\" {e}", route.handler))?;
+
+    let config = insert_route(&fs::read_to_string(&config_file)?, route)?;
+    let mut main = fs::read_to_string(&entry_file)?;
+    if declares(&main, &route.handler) {
+        bail!("{} already declares {}", ENTRY_FILE_NAME, route.handler);
+    }
+
+    let handlers_dir = project.join(HANDLERS_DIR_NAME);
+    let types_file = handlers_dir.join(TYPES_FILE_NAME);
+    let mut files = vec![(config_file, config)];
+    let stub = handler_stub(route);
+    let needs_types = own_file || !REQ_TYPE_REGEX.is_match(&main);
+    if own_file {
+        let handler_file = handlers_dir.join(format!("{}.ts", route.handler));
+        if handler_file.exists() {
+            bail!("{} already exists", handler_file.display());
+        }
+        files.push((
+            handler_file,
+            format!("import type {{ Req, Res }} from './{TYPES_FILE_NAME}';\n\nexport {stub}"),
+        ));
+        if !main.ends_with('\n') {
+            main.push('\n');
+        }
+        main.push_str(&format!(
+            "export {{ {0} }} from './{HANDLERS_DIR_NAME}/{0}.ts';\n",
+            route.handler
+        ));
+    } else {
+        if needs_types {
+            main.insert_str(
+                0,
+                &format!("import type {{ Req, Res }} from './{HANDLERS_DIR_NAME}/{TYPES_FILE_NAME}';\n\n"),
+            );
+        }
+        main = insert_handler(&main, &route.handler, &stub);
+    }
+    files.push((entry_file, main));
+    if needs_types && !types_file.exists() {
+        files.push((types_file, TYPES_FILE.to_string()));
+    }
+
+    let mut written = vec![];
+    for (file, content) in files {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, content).with_context(|| format!("Failed to write {}", file.display()))?;
+        written.push(file);
+    }
+    Ok(written)
+}
+
+fn handler_stub(route: &RouteConfig) -> String {
+    format!(
+        "async function {handler}(_req: Req): Promise<Res> {{
+  return {{
+    status: 200,
+    headers: {{ 'content-type': 'application/json' }},
+    body: JSON.stringify({{ route: {route} }}),
+  }};
+}}
+",
+        handler = route.handler,
+        route = serde_json::Value::from(format!("{} {}", route.method, route.path)),
+    )
+}
+
+/// Puts the stub before the module's export list and exports the handler from
+/// it, or exports the stub itself when there is no list.
+fn insert_handler(main: &str, handler: &str, stub: &str) -> String {
+    let Some(list) = EXPORT_LIST_REGEX.captures_iter(main).last() else {
+        let separator = if main.ends_with('\n') { "\n" } else { "\n\n" };
+        return format!("{main}{separator}export {stub}");
+    };
+    let (statement, names) = (list.get(0).unwrap(), list.get(1).unwrap());
+    let existing = names.as_str().trim().trim_end_matches(',');
+    let names = match (existing.is_empty(), existing.contains('\n')) {
+        (true, _) => format!(" {handler} "),
+        (false, true) => format!("\n  {},\n  {handler},\n", existing.trim()),
+        (false, false) => format!(" {existing}, {handler} "),
+    };
+    format!(
+        "{}{stub}\nexport {{{names}}};{}",
+        &main[..statement.start()],
+        &main[statement.end()..]
+    )
+}
+
+/// Appends a route to config.yml, keeping its comments and formatting.
+fn insert_route(config: &str, route: &RouteConfig) -> Result<String> {
+    let mut lines: Vec<String> = config.lines().map(str::to_string).collect();
+    let key = lines.iter().position(|line| ROUTE_KEY_REGEX.is_match(line));
+
+    let (at, indent) = match key {
+        Some(key) => {
+            if ROUTE_KEY_REGEX
+                .captures(&lines[key])
+                .unwrap()
+                .get(1)
+                .is_some()
+            {
+                lines[key] = "route:".to_string();
+            }
+            // the block ends at the next top-level key, after its last item line
+            let mut last = key;
+            let mut indent = None;
+            for (i, line) in lines.iter().enumerate().skip(key + 1) {
+                if line.trim().is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if !line.starts_with([' ', '\t', '-']) {
+                    break;
+                }
+                if indent.is_none() && line.trim_start().starts_with('-') {
+                    indent = Some(line[..line.len() - line.trim_start().len()].to_string());
+                }
+                last = i;
+            }
+            (last + 1, indent.unwrap_or_else(|| "  ".to_string()))
+        }
+        None => {
+            lines.push("route:".to_string());
+            (lines.len(), "  ".to_string())
+        }
+    };
+
+    let entry = [
+        format!("{indent}- path: {}", route.path),
+        format!("{indent}  method: {}", route.method),
+        format!("{indent}  handler: {}", route.handler),
+    ];
+    lines.splice(at..at, entry);
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+
+    // make sure the route reads back as written
+    let parsed: ProjectConfig = serde_yaml::from_str(&updated)
+        .with_context(|| format!("Failed to add the route to {CONFIG_FILE_NAME}"))?;
+    if parsed.route.last() != Some(route) {
+        bail!("Failed to add the route to {CONFIG_FILE_NAME}, please add it by hand");
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(method: &str, path: &str, handler: &str) -> RouteConfig {
+        RouteConfig {
+            path: path.to_string(),
+            method: method.to_string(),
+            handler: handler.to_string(),
+        }
+    }
+
+    #[test]
+    fn handler_name_should_follow_route() {
+        assert_eq!(handler_name("GET", "/api/users"), "getUsers");
+        assert_eq!(handler_name("DELETE", "/api/users/:id"), "deleteUsersById");
+        assert_eq!(
+            handler_name("POST", "/blog-posts/{post_id}"),
+            "postBlogPostsByPostId"
+        );
+        assert_eq!(handler_name("GET", "/"), "getIndex");
+
+        let routes = [route("GET", "/", "getIndex")];
+        assert!(validate_handler("getUsers", &routes).is_ok());
+        assert!(validate_handler("getIndex", &routes).is_err());
+        assert!(validate_handler("1st", &routes).is_err());
+        assert!(validate_route_path("api").is_err());
+        assert!(validate_route_path("/api/it's").is_err());
+    }
+
+    #[test]
+    fn handler_stub_should_escape_route() {
+        let stub = handler_stub(&route("GET", "/api/it's\\", "get"));
+        assert!(stub.contains(r#"JSON.stringify({ route: "GET /api/it's\\" })"#));
+    }
+
+    #[test]
+    fn insert_route_should_keep_formatting() -> Result<()> {
+        let config = "---\nname: demo\nroute:\n  # example routes\n  - path: /api/hello\n    method: GET\n    handler: hello\n\n# build settings\nbuild:\n  minify: true\n";
+        let updated = insert_route(config, &route("POST", "/api/users", "createUser"))?;
+        assert_eq!(
+            updated,
+            "---\nname: demo\nroute:\n  # example routes\n  - path: /api/hello\n    method: GET\n    handler: hello\n  - path: /api/users\n    method: POST\n    handler: createUser\n\n# build settings\nbuild:\n  minify: true\n"
+        );
+
+        let updated = insert_route("name: demo\nroute: []\n", &route("GET", "/", "index"))?;
+        assert_eq!(
+            updated,
+            "name: demo\nroute:\n  - path: /\n    method: GET\n    handler: index\n"
+        );
+        let updated = insert_route(
+            "name: demo\nroute:\n- path: /a\n  method: GET\n  handler: a\n",
+            &route("GET", "/b", "b"),
+        )?;
+        assert!(updated.ends_with("  handler: a\n- path: /b\n  method: GET\n  handler: b\n"));
+        let updated = insert_route("name: demo", &route("GET", "/", "index"))?;
+        assert_eq!(
+            updated,
+            "name: demo\nroute:\n  - path: /\n    method: GET\n    handler: index\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insert_handler_should_wire_export() {
+        let stub = "async function b() {}\n";
+        assert_eq!(
+            insert_handler("function a() {}\n\nexport { a };\n", "b", stub),
+            "function a() {}\n\nasync function b() {}\n\nexport { a, b };\n"
+        );
+        assert_eq!(
+            insert_handler("export {\n  a,\n  c,\n};\n", "b", stub),
+            "async function b() {}\n\nexport {\n  a,\n  c,\n  b,\n};\n"
+        );
+        assert_eq!(
+            insert_handler(
+                "export function a() {}\nexport { x } from './x.ts';\n",
+                "b",
+                stub
+            ),
+            "export function a() {}\nexport { x } from './x.ts';\n\nexport async function b() {}\n"
+        );
+    }
+
+    #[test]
+    fn add_route_should_scaffold_handlers() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join(CONFIG_FILE_NAME),
+            "---\nname: demo\nroute:\n  - path: /api/hello\n    method: GET\n    handler: hello\n",
+        )?;
+        fs::write(
+            temp_dir.join(ENTRY_FILE_NAME),
+            "async function hello(req: object): Promise<object> {\n  return { status: 200, headers: {}, body: 'hi' };\n}\n\nexport { hello };\n",
+        )?;
+
+        add_route(&temp_dir, &route("GET", "/api/users", "getUsers"), false)?;
+        add_route(&temp_dir, &route("POST", "/api/users", "createUser"), true)?;
+        let main = fs::read_to_string(temp_dir.join(ENTRY_FILE_NAME))?;
+        assert!(main.starts_with("import type { Req, Res } from './handlers/types.ts';\n"));
+        assert!(main.contains("async function getUsers(_req: Req): Promise<Res> {"));
+        assert!(main.contains("export { hello, getUsers };\n"));
+        assert!(main.ends_with("export { createUser } from './handlers/createUser.ts';\n"));
+        let handler = fs::read_to_string(temp_dir.join("handlers/createUser.ts"))?;
+        assert!(handler.contains("export async function createUser(_req: Req): Promise<Res> {"));
+        assert!(temp_dir.join("handlers/types.ts").is_file());
+        let routes = ProjectConfig::load(&temp_dir)?.route;
+        assert_eq!(routes.len(), 3);
+        assert_eq!(routes[2], route("POST", "/api/users", "createUser"));
+
+        // duplicates are refused
+        assert!(add_route(&temp_dir, &route("GET", "/api/users", "listUsers"), false).is_err());
+        assert!(add_route(&temp_dir, &route("PUT", "/api/users", "hello"), false).is_err());
+
+        // the handlers are bundled and exported
+        let bundle = crate::run_bundle(
+            temp_dir.join(ENTRY_FILE_NAME).to_str().unwrap(),
+            &Default::default(),
+        )?;
+        let worker = crate::JsWorker::try_new(&bundle)?;
+        for handler in ["hello", "getUsers", "createUser"] {
+            let req = crate::Req::builder()
+                .method("GET")
+                .url("https://example.com/api/users")
+                .headers(Default::default())
+                .build();
+            assert_eq!(worker.run_http(handler, req)?.status, 200, "{handler}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use git2::Repository;
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use serde::Deserialize;
+
+use super::routes::TYPES_FILE;
+use crate::RouteConfig;
+
+pub const DEFAULT_TEMPLATE: &str = "default";
+/// Describes a template, optional for template directories.
+const MANIFEST_FILE_NAME: &str = "template.yml";
+/// Files with this suffix are rendered, the others are copied as is.
+const TEMPLATE_SUFFIX: &str = ".j2";
+
+macro_rules! starter {
+    ($name:literal: $($file:literal),+) => {
+        (
+            $name,
+            &[$((
+                $file,
+                include_str!(concat!("../../templates/starters/", $name, "/", $file)),
+            )),+],
+        )
+    };
+}
+
+/// Starters shipped with dino, usable by name with `dino init --template`.
+pub const BUILTIN_TEMPLATES: [(&str, &[(&str, &str)]); 5] = [
+    starter!("default": "template.yml", "config.yml.j2", "main.ts.j2", ".gitignore.j2"),
+    starter!("api": "template.yml", "config.yml.j2", "main.ts.j2", ".gitignore.j2"),
+    starter!("site": "template.yml", "config.yml.j2", "main.ts.j2", "index.html.j2", ".gitignore.j2"),
+    starter!("job": "template.yml", "config.yml.j2", "main.ts.j2", ".gitignore.j2"),
+    starter!("test": "template.yml", "config.yml.j2", "main.ts.j2", "main.test.ts.j2", ".gitignore.j2"),
+];
+
+/// Handler stubs of the `routes`, included by the starters' main.ts. Also
+/// declares `Req` and `Res` unless `declares_handler_types` is set.
+const HANDLERS_TEMPLATE: (&str, &str) = (
+    "handlers.ts.j2",
+    include_str!("../../templates/starters/handlers.ts.j2"),
+);
+
+/// Files `dino init` can add to any template.
+const EXTRA_FILES: [(&str, &str); 2] = [
+    (
+        "main.test.ts.j2",
+        include_str!("../../templates/extras/main.test.ts.j2"),
+    ),
+    (
+        "tsconfig.json",
+        include_str!("../../templates/extras/tsconfig.json"),
+    ),
+];
+
+/// A template's `template.yml`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TemplateManifest {
+    #[serde(default)]
+    pub description: String,
+    /// Variables used by the template besides `name`.
+    #[serde(default)]
+    pub variables: Vec<TemplateVariable>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateVariable {
+    pub name: String,
+    /// Question asked for the value when `dino init` is interactive.
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub default: String,
+}
+
+/// Files scaffolding a project, rendered with [`ProjectTemplate::render`].
+#[derive(Debug)]
+pub struct ProjectTemplate {
+    pub manifest: TemplateManifest,
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl ProjectTemplate {
+    /// Loads a built-in template by name, or a template from a local
+    /// directory or a git repository URL.
+    pub fn load(source: &str) -> Result<Self> {
+        if is_git_url(source) {
+            Self::from_git(source)
+        } else if Path::new(source).is_dir() {
+            Self::from_dir(Path::new(source))
+        } else {
+            Self::builtin(source)
+        }
+    }
+
+    fn builtin(name: &str) -> Result<Self> {
+        let Some((_, files)) = BUILTIN_TEMPLATES.iter().find(|(n, _)| *n == name) else {
+            let mut available = String::new();
+            for (name, files) in BUILTIN_TEMPLATES {
+                let template = Self::from_builtin_files(files)?;
+                available.push_str(&format!("\n  {name:<10}{}", template.manifest.description));
+            }
+            bail!("Unknown template \"{name}\", use a directory, a git URL or one of:{available}");
+        };
+        Self::from_builtin_files(files)
+    }
+
+    /// Loads one of the extra files as a template, e.g. `tsconfig.json`.
+    pub fn extra(file: &str) -> Result<Self> {
+        let Some(extra) = EXTRA_FILES
+            .iter()
+            .find(|(name, _)| name.strip_suffix(TEMPLATE_SUFFIX).unwrap_or(name) == file)
+        else {
+            bail!("Unknown extra file \"{file}\"");
+        };
+        Self::from_builtin_files(&[*extra])
+    }
+
+    /// Whether the template has a file at this path once rendered.
+    pub fn contains(&self, file: &str) -> bool {
+        self.files.iter().any(|(path, _)| {
+            path.to_str()
+                .map(|path| path.strip_suffix(TEMPLATE_SUFFIX).unwrap_or(path))
+                == Some(file)
+        })
+    }
+
+    fn from_builtin_files(files: &[(&str, &str)]) -> Result<Self> {
+        Self::from_files(
+            files
+                .iter()
+                .map(|(file, content)| (PathBuf::from(file), content.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    fn from_dir(path: &Path) -> Result<Self> {
+        let mut files = vec![];
+        collect_files(path, Path::new(""), &mut files)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        Self::from_files(files)
+    }
+
+    fn from_git(url: &str) -> Result<Self> {
+        let temp_dir = tempfile::TempDir::new()?;
+        Repository::clone(url, temp_dir.path())
+            .with_context(|| format!("Failed to clone template {url}"))?;
+        Self::from_dir(temp_dir.path())
+    }
+
+    fn from_files(mut files: Vec<(PathBuf, Vec<u8>)>) -> Result<Self> {
+        let manifest = match files
+            .iter()
+            .position(|(file, _)| file == Path::new(MANIFEST_FILE_NAME))
+        {
+            Some(index) => serde_yaml::from_slice(&files.remove(index).1)
+                .with_context(|| format!("Invalid {MANIFEST_FILE_NAME}"))?,
+            None => TemplateManifest::default(),
+        };
+        Ok(Self { manifest, files })
+    }
+
+    /// Renders the `.j2` files with the given variables, dropping the suffix,
+    /// and returns them with the other files. `routes` is available to the
+    /// templates as a list of `path`, `method` and `handler`, and
+    /// `{% include "handlers.ts.j2" %}` renders a stub for each.
+    pub fn render(
+        &self,
+        vars: &BTreeMap<String, String>,
+        routes: &[RouteConfig],
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_keep_trailing_newline(true);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_template(HANDLERS_TEMPLATE.0, HANDLERS_TEMPLATE.1)?;
+        env.add_template("handler_types.ts", TYPES_FILE)?;
+        let ctx = context! { routes => routes, ..Value::from_serialize(vars) };
+
+        self.files
+            .iter()
+            .map(|(file, content)| {
+                let Some(target) = file
+                    .to_str()
+                    .and_then(|file| file.strip_suffix(TEMPLATE_SUFFIX))
+                else {
+                    return Ok((file.clone(), content.clone()));
+                };
+                let source = std::str::from_utf8(content)
+                    .with_context(|| format!("{} is not valid UTF-8", file.display()))?;
+                let rendered = env
+                    .render_str(source, &ctx)
+                    .with_context(|| format!("Failed to render {}", file.display()))?;
+                Ok((PathBuf::from(target), rendered.into_bytes()))
+            })
+            .collect()
+    }
+}
+
+fn is_git_url(source: &str) -> bool {
+    ["https://", "http://", "ssh://", "git://", "file://", "git@"]
+        .iter()
+        .any(|prefix| source.starts_with(prefix))
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            if entry.file_name() != ".git" {
+                collect_files(root, &path, files)?;
+            }
+        } else {
+            files.push((path, fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_templates_should_render() -> Result<()> {
+        for (name, _) in BUILTIN_TEMPLATES {
+            let template = ProjectTemplate::load(name)?;
+            assert!(!template.manifest.description.is_empty(), "{name}");
+
+            let mut vars: BTreeMap<_, _> = template
+                .manifest
+                .variables
+                .iter()
+                .map(|var| (var.name.clone(), var.default.clone()))
+                .collect();
+            vars.insert("name".to_string(), "demo".to_string());
+            let routes = [RouteConfig {
+                path: "/api/todos".to_string(),
+                method: "GET".to_string(),
+                handler: "listTodos".to_string(),
+            }];
+            let files = template.render(&vars, &routes)?;
+            assert!(files.iter().all(|(file, _)| !file.ends_with(".j2")));
+
+            let file = |name: &str| {
+                let (_, content) = files
+                    .iter()
+                    .find(|(file, _)| file == Path::new(name))
+                    .expect(name);
+                String::from_utf8_lossy(content).to_string()
+            };
+            let config: crate::ProjectConfig = serde_yaml::from_str(&file("config.yml"))?;
+            assert_eq!(config.name, "demo");
+            assert_eq!(config.route.last(), Some(&routes[0]), "{name}");
+            let main = file("main.ts");
+            assert!(
+                main.contains("async function listTodos(_req: Req): Promise<Res> {"),
+                "{name}"
+            );
+            assert!(main.contains(r#"route: "GET /api/todos""#), "{name}");
+            assert!(main.contains(", listTodos };"), "{name}");
+            assert_eq!(main.matches("interface Req ").count(), 1, "{name}");
+            assert_eq!(file(".gitignore"), "build/\n");
+        }
+        assert!(ProjectTemplate::load("unknown").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn template_dir_should_render_variables() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::create_dir_all(temp_dir.join("src"))?;
+        fs::write(
+            temp_dir.join(MANIFEST_FILE_NAME),
+            "variables:\n  - name: greeting\n    default: Hi\n",
+        )?;
+        fs::write(
+            temp_dir.join("src/main.ts.j2"),
+            "// {{ greeting }}, {{ name }}\n",
+        )?;
+        fs::write(temp_dir.join("logo.txt"), "{{ untouched }}")?;
+
+        let template = ProjectTemplate::load(temp_dir.to_str().unwrap())?;
+        assert_eq!(template.manifest.variables[0].name, "greeting");
+
+        let vars = BTreeMap::from([
+            ("name".to_string(), "demo".to_string()),
+            ("greeting".to_string(), "Hello".to_string()),
+        ]);
+        let mut files = template.render(&vars, &[])?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                (PathBuf::from("logo.txt"), b"{{ untouched }}".to_vec()),
+                (PathBuf::from("src/main.ts"), b"// Hello, demo\n".to_vec()),
+            ]
+        );
+
+        // variables must be defined
+        assert!(template
+            .render(
+                &BTreeMap::from([("name".to_string(), "demo".to_string())]),
+                &[]
+            )
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,546 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::{
+    coverage::CoverageReport,
+    source_options,
+    test_report::{JUnitReporter, Reporter, ReporterKind, SourceMaps, TestSummary},
+    CmdExector, BUILD_DIR_NAME, ENTRY_FILE_NAME,
+};
+use crate::{
+    bundle, JsWorker, Options, ProjectConfig, RouteConfig, TestReport, DEFAULT_PROFILE,
+    HANDLERS_MODULE_NAME,
+};
+
+/// Assets imported by tests, e.g. WebAssembly, go to `build/test`.
+const TEST_DIR_NAME: &str = "test";
+
+lazy_static! {
+    // Same as the `test` module's `TEST_FILE`.
+    static ref TEST_FILE_REGEX: Regex = Regex::new(r"\.(spec|test)\.[jt]s$").unwrap();
+}
+
+#[derive(Debug, Parser)]
+pub struct TestOpts {
+    /// Only run tests whose description matches this regular expression
+    pub filter: Option<String>,
+    /// Stop a test file at its first failing test
+    #[arg(long)]
+    pub fail_fast: bool,
+    /// How to print the results
+    #[arg(long, value_enum, default_value_t)]
+    pub reporter: ReporterKind,
+    /// Also write the results as JUnit XML to this file
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<PathBuf>,
+    /// Report which lines and branches of the project the tests ran, in
+    /// build/coverage/lcov.info and a summary
+    #[arg(long)]
+    pub coverage: bool,
+}
+
+impl CmdExector for TestOpts {
+    async fn execute(self) -> Result<()> {
+        let path = env::current_dir()?;
+        let mut reporters = vec![self.reporter.reporter()];
+        if let Some(junit) = &self.junit {
+            reporters.push(Box::new(JUnitReporter::new(junit)));
+        }
+        let mut coverage = self.coverage.then(CoverageReport::default);
+        let summary = test_project(
+            &path,
+            self.filter.as_deref(),
+            self.fail_fast,
+            coverage.as_mut(),
+            &mut reporters,
+        )?;
+        if let Some(coverage) = coverage {
+            let file = coverage.write_lcov(&path)?;
+            // keeps machine-readable results on stdout parseable
+            let summary = format!(
+                "\n{}\nCoverage written to {}",
+                coverage.summary(&path),
+                file.display()
+            );
+            match self.reporter {
+                ReporterKind::Pretty => println!("{summary}"),
+                _ => eprintln!("{summary}"),
+            }
+        }
+        if summary.failed > 0 || summary.errors > 0 {
+            bail!(
+                "{} tests failed, {} test files couldn't run",
+                summary.failed,
+                summary.errors
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Bundles each test file of the project and runs it in its own worker.
+/// With `coverage`, the project's modules are instrumented and the counters
+/// of all the workers, test files aside, are added to it.
+fn test_project(
+    path: &Path,
+    filter: Option<&str>,
+    fail_fast: bool,
+    mut coverage: Option<&mut CoverageReport>,
+    reporter: &mut dyn Reporter,
+) -> Result<TestSummary> {
+    let started = Instant::now();
+    let build_path = path.join(BUILD_DIR_NAME);
+    let project = ProjectConfig::load(path)?;
+    let config = project.profile(DEFAULT_PROFILE)?;
+    let options = Options {
+        asset_dir: Some(build_path.join(TEST_DIR_NAME)),
+        // to report where tests and errors are in the sources, which modules
+        // from the transpile cache have lost, so it isn't used
+        source_map: true,
+        coverage: coverage.is_some(),
+        ..source_options(path, &project, &config)?
+    };
+
+    // Tests run next to the handlers of main.ts, so that `request()` can
+    // reach them through the routes. Test files bundle their own copy of
+    // the modules they import, main.ts included, which doesn't share state
+    // with the handlers'.
+    let entry = path.join(ENTRY_FILE_NAME);
+    let mut source_maps = SourceMaps::new(path);
+    let handlers = match entry.exists() {
+        true => {
+            let output = bundle(&entry.display().to_string(), &options)
+                .with_context(|| format!("Failed to bundle {ENTRY_FILE_NAME} for the tests"))?;
+            if let Some(map) = &output.source_map {
+                source_maps.insert(HANDLERS_MODULE_NAME, map)?;
+            }
+            if let Some(coverage) = coverage.as_deref_mut() {
+                coverage.add_maps(&output.coverage);
+            }
+            output.code
+        }
+        false => String::new(),
+    };
+    let project = TestProject {
+        handlers: &handlers,
+        routes: &project.route,
+        options: &options,
+        filter,
+        fail_fast,
+    };
+
+    let mut summary = TestSummary::default();
+    let files = find_test_files(path)?;
+    for file in &files {
+        let name = file
+            .strip_prefix(path)
+            .unwrap_or(file)
+            .display()
+            .to_string();
+        reporter.start_file(&name)?;
+        match run_test_file(
+            file,
+            &name,
+            &project,
+            &mut source_maps,
+            coverage.as_deref_mut(),
+        ) {
+            Ok(report) => {
+                summary.ok += report.ok;
+                summary.failed += report.failed;
+                summary.ignored += report.ignored;
+                reporter.report_file(&name, Ok(&report))?;
+            }
+            Err(e) => {
+                summary.errors += 1;
+                let message = source_maps.map_text(&format!("{e:#}"));
+                reporter.report_file(&name, Err(&message))?;
+            }
+        }
+        summary.files += 1;
+    }
+
+    if let Some(coverage) = coverage {
+        coverage.retain(|file| !TEST_FILE_REGEX.is_match(file));
+    }
+    summary.duration = started.elapsed();
+    reporter.finish(&summary)?;
+    Ok(summary)
+}
+
+/// What a test file runs with.
+struct TestProject<'a> {
+    /// Bundle of main.ts, loaded as the handlers.
+    handlers: &'a str,
+    routes: &'a [RouteConfig],
+    options: &'a Options,
+    filter: Option<&'a str>,
+    fail_fast: bool,
+}
+
+/// Runs a test file, loaded under `name`, and maps its results back to the
+/// sources.
+fn run_test_file(
+    file: &Path,
+    name: &str,
+    project: &TestProject,
+    source_maps: &mut SourceMaps,
+    coverage: Option<&mut CoverageReport>,
+) -> Result<TestReport> {
+    let options = project.options;
+    let output = bundle(&file.display().to_string(), options)?;
+    if let Some(map) = &output.source_map {
+        source_maps.insert(name, map)?;
+    }
+    let worker = JsWorker::try_new_with_format(project.handlers, options.format)?;
+    worker.set_routes(project.routes)?;
+    worker.load(name, &output.code, options.format)?;
+    let mut report = worker.run_tests(project.filter, project.fail_fast)?;
+    source_maps.map_report(&mut report);
+    if let Some(coverage) = coverage {
+        coverage.add_maps(&output.coverage);
+        coverage.add_counters(&worker.coverage()?);
+    }
+    Ok(report)
+}
+
+/// Finds `*.test.ts`, `*.spec.ts` and their `.js` variants, skipping builds,
+/// dependencies and hidden directories.
+fn find_test_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                if !name.starts_with('.') && name != BUILD_DIR_NAME && name != "node_modules" {
+                    dirs.push(entry.path());
+                }
+            } else if TEST_FILE_REGEX.is_match(&name) {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputFormat;
+
+    #[test]
+    fn test_project_should_run_test_files() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::create_dir_all(temp_dir.join("lib"))?;
+        fs::create_dir_all(temp_dir.join("build"))?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            "export function add(a: number, b: number): number {\n  return a + b;\n}\n",
+        )?;
+        fs::write(
+            temp_dir.join("main.test.ts"),
+            r#"
+            import test from 'test';
+            import assert from 'assert';
+            import { add } from './main.ts';
+
+            test('add', () => assert.equal(add(1, 2), 3));
+            test('add async', async () => {
+                const sum = await new Promise((resolve) => setTimeout(() => resolve(add(2, 2)), 10));
+                assert.equal(sum, 4);
+            });
+            test('add is broken', () => assert.equal(add(1, 1), 3));
+            test('skipped', { ignore: true }, () => {});
+            "#,
+        )?;
+        fs::write(
+            temp_dir.join("lib/slow.spec.js"),
+            r#"
+            import test from 'test';
+
+            test('times out', { timeout: 20 }, () => new Promise(() => {}));
+            "#,
+        )?;
+        fs::write(
+            temp_dir.join("lib/broken.test.ts"),
+            "import './missing.ts';",
+        )?;
+        // builds aren't searched
+        fs::write(temp_dir.join("build/old.test.js"), "throw new Error();")?;
+
+        assert_eq!(
+            find_test_files(&temp_dir)?,
+            vec![
+                temp_dir.join("lib/broken.test.ts"),
+                temp_dir.join("lib/slow.spec.js"),
+                temp_dir.join("main.test.ts"),
+            ]
+        );
+
+        let mut reporters: Vec<Box<dyn Reporter>> = vec![];
+        let summary = test_project(&temp_dir, None, false, None, &mut reporters)?;
+        assert_eq!(
+            (
+                summary.files,
+                summary.ok,
+                summary.failed,
+                summary.ignored,
+                summary.errors
+            ),
+            (3, 2, 2, 1, 1)
+        );
+
+        let summary = test_project(&temp_dir, Some("^add"), false, None, &mut reporters)?;
+        assert_eq!((summary.ok, summary.failed, summary.ignored), (2, 1, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_should_route_requests() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("config.yml"),
+            r#"
+name: users
+route:
+  - path: /api/users/{id}
+    method: GET
+    handler: getUser
+  - path: /api/users
+    method: POST
+    handler: createUser
+"#,
+        )?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            async function getUser(req) {
+                return { status: 200, headers: {}, body: `${req.params.id} ${req.url}` };
+            }
+            async function createUser(req) {
+                const user = JSON.parse(req.body);
+                return { status: 201, headers: { 'content-type': req.header['content-type'] }, body: user.name };
+            }
+            export { getUser, createUser };
+            "#,
+        )?;
+        fs::write(
+            temp_dir.join("main.test.ts"),
+            r#"
+            import test, { request } from 'test';
+            import assert from 'assert';
+
+            test('get', async () => {
+                const res = await request('/api/users/42?full=1');
+                assert.equal(res.status, 200);
+                assert.equal(res.body, '42 http://localhost/api/users/42?full=1');
+            });
+            test('post', async () => {
+                const res = await request('/api/users', { method: 'post', body: { name: 'Ann' } });
+                assert.equal(res.status, 201);
+                assert.equal(res.headers['content-type'], 'application/json');
+                assert.equal(res.body, 'Ann');
+            });
+            test('unknown routes', async () => {
+                assert.equal((await request('/api/posts')).status, 404);
+                assert.equal((await request('/api/users', { method: 'DELETE' })).status, 405);
+            });
+            "#,
+        )?;
+
+        let summary = test_project(&temp_dir, None, false, None, &mut vec![])?;
+        assert_eq!((summary.ok, summary.failed, summary.errors), (3, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_should_keep_main_apart_from_handlers() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("config.yml"),
+            "name: hits\nroute:\n  - path: /api/hit\n    method: POST\n    handler: hit\n",
+        )?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            let hits = 0;
+            export function count() {
+                return hits;
+            }
+            export async function hit() {
+                hits++;
+                return { status: 200, headers: {}, body: String(hits) };
+            }
+            "#,
+        )?;
+        fs::write(
+            temp_dir.join("main.test.ts"),
+            r#"
+            import test, { request } from 'test';
+            import assert from 'assert';
+            import { count } from './main.ts';
+
+            test('hits', async () => {
+                assert.equal((await request('/api/hit', { method: 'POST' })).body, '1');
+                // the handler updated its own copy of main.ts
+                assert.equal(count(), 0);
+            });
+            "#,
+        )?;
+
+        let summary = test_project(&temp_dir, None, false, None, &mut vec![])?;
+        assert_eq!((summary.ok, summary.failed, summary.errors), (1, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_runner_should_exit_when_run_directly() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let file = temp_dir.join("main.ts");
+        fs::write(
+            &file,
+            r#"
+            import test, { mainRunner } from 'test';
+
+            test('passes', () => {});
+            test('fails', () => {
+                throw new Error('failed');
+            });
+
+            export async function run() {
+                let code;
+                process.exit = (exitCode) => (code = exitCode);
+                await mainRunner.run();
+                return code;
+            }
+            "#,
+        )?;
+
+        let options = Options {
+            format: OutputFormat::Esm,
+            ..Default::default()
+        };
+        let output = bundle(&file.display().to_string(), &options)?;
+        let worker = JsWorker::try_new_with_format(&output.code, options.format)?;
+        assert_eq!(worker.eval("await handlers.run()")?.as_deref(), Some("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_should_define_build_metadata() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(temp_dir.join("config.yml"), "name: app\nversion: 1.2.0\n")?;
+        fs::write(
+            temp_dir.join("main.test.ts"),
+            r#"
+            import test from 'test';
+            import assert from 'assert';
+
+            test('metadata', () => {
+                assert.equal(__DINO_BUILD__.name, 'app');
+                assert.equal(__DINO_BUILD__.version, '1.2.0');
+                assert.false(Number.isNaN(Date.parse(__DINO_BUILD__.built_at)));
+            });
+            "#,
+        )?;
+
+        let summary = test_project(&temp_dir, None, false, None, &mut vec![])?;
+        assert_eq!((summary.ok, summary.failed, summary.errors), (1, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_should_collect_coverage() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"export function sign(n: number): string {
+  if (n < 0) {
+    return "-";
+  }
+  return "+";
+}
+"#,
+        )?;
+        fs::write(
+            temp_dir.join("main.test.ts"),
+            r#"
+            import test from 'test';
+            import assert from 'assert';
+            import { sign } from './main.ts';
+
+            test('sign', () => assert.equal(sign(1), '+'));
+            "#,
+        )?;
+
+        let mut coverage = CoverageReport::default();
+        let summary = test_project(&temp_dir, None, false, Some(&mut coverage), &mut vec![])?;
+        assert_eq!((summary.ok, summary.failed, summary.errors), (1, 0, 0));
+        // test files aren't reported, the handlers and the test's copy of
+        // main.ts count together
+        let lcov = fs::read_to_string(coverage.write_lcov(&temp_dir)?)?;
+        assert_eq!(
+            lcov,
+            "TN:\nSF:main.ts\nDA:2,1\nDA:3,0\nDA:5,1\nLF:3\nLH:2\n\
+             BRDA:2,0,0,0\nBRDA:2,0,1,1\nBRF:2\nBRH:1\nend_of_record\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_test_file_should_report_results() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let file = temp_dir.join("a.test.ts");
+        fs::write(
+            &file,
+            r#"
+            import test from 'test';
+            import assert from 'assert';
+
+            test('first', () => {});
+            test('second', () => assert.equal(1, 2, 'one is not two'));
+            test('third', () => {});
+            "#,
+        )?;
+        let options = Options {
+            format: OutputFormat::Esm,
+            source_map: true,
+            ..Default::default()
+        };
+        let project = TestProject {
+            handlers: "",
+            routes: &[],
+            options: &options,
+            filter: None,
+            fail_fast: true,
+        };
+
+        let mut source_maps = SourceMaps::new(&temp_dir);
+        let report = run_test_file(&file, "a.test.ts", &project, &mut source_maps, None)?;
+        assert_eq!((report.ok, report.failed, report.ignored), (1, 1, 1));
+        let failed = &report.results[1];
+        assert_eq!(failed.description, "second");
+        assert_eq!(failed.status, crate::TestStatus::Failed);
+        let error = failed.error.as_ref().unwrap();
+        assert_eq!(error.message, "one is not two");
+        // positions in the bundle are mapped back to the test file
+        assert_eq!(
+            report.results[0].location.as_deref(),
+            Some("a.test.ts:5:13")
+        );
+        assert_eq!(failed.location.as_deref(), Some("a.test.ts:6:13"));
+        assert!(error.stack.as_deref().unwrap().contains("(a.test.ts:6:41)"));
+        Ok(())
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde_json::json;
+use sourcemap::SourceMap;
+
+use crate::{TestReport, TestResult, TestStatus};
+
+lazy_static! {
+    /// A `file:line:column` position, as in the frames of QuickJS stacks.
+    static ref POSITION_REGEX: Regex = Regex::new(r"([^\s()]+):(\d+):(\d+)").unwrap();
+}
+
+/// Totals of a `dino test` run.
+#[derive(Debug, Default)]
+pub(super) struct TestSummary {
+    pub files: usize,
+    pub ok: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    /// Test files that couldn't be bundled or loaded.
+    pub errors: usize,
+    pub duration: Duration,
+}
+
+/// Maps positions in the bundles loaded by a worker back to their sources.
+pub(super) struct SourceMaps {
+    /// Sources under it are shown relative to it.
+    root: PathBuf,
+    /// By the name the bundle is loaded under.
+    maps: HashMap<String, SourceMap>,
+}
+
+impl SourceMaps {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            maps: HashMap::new(),
+        }
+    }
+
+    /// Adds the source map of the bundle loaded under `name`.
+    pub fn insert(&mut self, name: &str, source_map: &str) -> Result<()> {
+        let map = SourceMap::from_slice(source_map.as_bytes())
+            .with_context(|| format!("Invalid source map for {name}"))?;
+        self.maps.insert(name.to_string(), map);
+        Ok(())
+    }
+
+    /// Replaces the positions in known bundles with their positions in the
+    /// sources, e.g. in stacks.
+    pub fn map_text(&self, text: &str) -> String {
+        POSITION_REGEX
+            .replace_all(text, |caps: &Captures| {
+                let position = caps[2].parse().ok().zip(caps[3].parse().ok());
+                position
+                    .and_then(|(line, column)| self.map_position(&caps[1], line, column))
+                    .unwrap_or_else(|| caps[0].to_string())
+            })
+            .into_owned()
+    }
+
+    /// Maps the locations and stacks of the results.
+    pub fn map_report(&self, report: &mut TestReport) {
+        for result in &mut report.results {
+            if let Some(location) = &mut result.location {
+                *location = self.map_text(location);
+            }
+            if let Some(stack) = result.error.as_mut().and_then(|e| e.stack.as_mut()) {
+                *stack = self.map_text(stack);
+            }
+        }
+    }
+
+    /// Maps a 1-based position, as QuickJS reports them.
+    fn map_position(&self, file: &str, line: u32, column: u32) -> Option<String> {
+        let map = self.maps.get(file)?;
+        let position = (line.checked_sub(1)?, column.checked_sub(1)?);
+        // The end of a statement and the start of the next one can share a
+        // position, the last token is the one starting there.
+        let token = map
+            .tokens()
+            .take_while(|token| (token.get_dst_line(), token.get_dst_col()) <= position)
+            .last()?;
+        let source = token.get_source()?;
+        let source = source.strip_prefix("file://").unwrap_or(source);
+        let source = Path::new(source)
+            .strip_prefix(&self.root)
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| source.to_string());
+        Some(format!(
+            "{source}:{}:{}",
+            token.get_src_line() + 1,
+            token.get_src_col() + 1
+        ))
+    }
+}
+
+/// How `dino test` prints the results.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReporterKind {
+    /// Coloured OK/FAIL lines and a summary.
+    #[default]
+    Pretty,
+    /// Test Anything Protocol, version 13.
+    Tap,
+    /// One JSON object per test, file error and summary.
+    Json,
+}
+
+impl ReporterKind {
+    pub(super) fn reporter(self) -> Box<dyn Reporter> {
+        match self {
+            Self::Pretty => Box::new(PrettyReporter::new(io::stdout())),
+            Self::Tap => Box::new(TapReporter::new(io::stdout())),
+            Self::Json => Box::new(JsonReporter::new(io::stdout())),
+        }
+    }
+}
+
+/// Receives the results of `dino test` as the test files run.
+pub(super) trait Reporter {
+    fn start_file(&mut self, _file: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reports the results of a test file, or why it couldn't run.
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()>;
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()>;
+}
+
+impl Reporter for Vec<Box<dyn Reporter>> {
+    fn start_file(&mut self, file: &str) -> Result<()> {
+        self.iter_mut().try_for_each(|r| r.start_file(file))
+    }
+
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()> {
+        self.iter_mut()
+            .try_for_each(|r| r.report_file(file, report))
+    }
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()> {
+        self.iter_mut().try_for_each(|r| r.finish(summary))
+    }
+}
+
+pub(super) struct PrettyReporter<W> {
+    out: W,
+}
+
+impl<W: Write> PrettyReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Reporter for PrettyReporter<W> {
+    fn start_file(&mut self, file: &str) -> Result<()> {
+        writeln!(self.out, "{} {file}", "Running".cyan())?;
+        Ok(())
+    }
+
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()> {
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                writeln!(self.out, "{} {file}\n {}", "Error".red(), e.red())?;
+                return Ok(());
+            }
+        };
+        for result in &report.results {
+            let duration = format!("({} ms)", result.duration as u64).bright_black();
+            match result.status {
+                TestStatus::Ok => writeln!(
+                    self.out,
+                    "{} {} {duration}",
+                    " OK ".on_green().bold(),
+                    result.description.green()
+                )?,
+                TestStatus::Ignored => writeln!(
+                    self.out,
+                    "{} {}",
+                    " IGNORED ".on_yellow().bold(),
+                    result.description.yellow()
+                )?,
+                TestStatus::Failed => {
+                    let location = result.location.as_deref().unwrap_or(file);
+                    writeln!(
+                        self.out,
+                        "{} {} {duration}\n at {location}",
+                        " FAIL ".on_red().bold(),
+                        result.description.red()
+                    )?;
+                    if let Some(error) = &result.error {
+                        let mut details = format!("{}: {}", error.name, error.message);
+                        if let Some(stack) = &error.stack {
+                            details.push('\n');
+                            details.push_str(stack);
+                        }
+                        writeln!(self.out, " {}", details.red())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()> {
+        let result = format!(
+            "{} ok; {} failed; {} ignored",
+            summary.ok, summary.failed, summary.ignored
+        );
+        let result = match summary.failed + summary.errors {
+            0 => result.green(),
+            _ => result.red(),
+        };
+        writeln!(
+            self.out,
+            "\nTest result: {result} in {} files ({} ms)",
+            summary.files,
+            summary.duration.as_millis()
+        )?;
+        Ok(())
+    }
+}
+
+pub(super) struct TapReporter<W> {
+    out: W,
+    started: bool,
+    /// Test points written so far.
+    count: usize,
+}
+
+impl<W: Write> TapReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: false,
+            count: 0,
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            writeln!(self.out, "TAP version 13")?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Writes a YAML block with the details of a failure.
+    fn write_details(&mut self, fields: &[(&str, Option<&str>)]) -> Result<()> {
+        writeln!(self.out, "  ---")?;
+        for (key, value) in fields {
+            let Some(value) = value else {
+                continue;
+            };
+            if value.contains('\n') {
+                writeln!(self.out, "  {key}: |-")?;
+                for line in value.lines() {
+                    writeln!(self.out, "    {line}")?;
+                }
+            } else {
+                // JSON strings are valid YAML
+                writeln!(self.out, "  {key}: {}", json!(value))?;
+            }
+        }
+        writeln!(self.out, "  ...")?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Reporter for TapReporter<W> {
+    fn start_file(&mut self, file: &str) -> Result<()> {
+        self.start()?;
+        writeln!(self.out, "# {file}")?;
+        Ok(())
+    }
+
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()> {
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                self.count += 1;
+                writeln!(self.out, "not ok {} - {file}", self.count)?;
+                return self.write_details(&[("message", Some(e))]);
+            }
+        };
+        for result in &report.results {
+            self.count += 1;
+            let description = result.description.replace('#', "\\#");
+            match result.status {
+                TestStatus::Ok => writeln!(
+                    self.out,
+                    "ok {} - {description} # time={:.3}ms",
+                    self.count, result.duration
+                )?,
+                TestStatus::Ignored => {
+                    writeln!(self.out, "ok {} - {description} # SKIP", self.count)?
+                }
+                TestStatus::Failed => {
+                    writeln!(
+                        self.out,
+                        "not ok {} - {description} # time={:.3}ms",
+                        self.count, result.duration
+                    )?;
+                    let error = result.error.as_ref();
+                    self.write_details(&[
+                        ("name", error.map(|e| e.name.as_str())),
+                        ("message", error.map(|e| e.message.as_str())),
+                        ("at", result.location.as_deref()),
+                        ("stack", error.and_then(|e| e.stack.as_deref())),
+                    ])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()> {
+        self.start()?;
+        writeln!(self.out, "1..{}", self.count)?;
+        writeln!(self.out, "# ok {}", summary.ok)?;
+        writeln!(self.out, "# failed {}", summary.failed)?;
+        writeln!(self.out, "# ignored {}", summary.ignored)?;
+        writeln!(self.out, "# errors {}", summary.errors)?;
+        Ok(())
+    }
+}
+
+pub(super) struct JsonReporter<W> {
+    out: W,
+}
+
+impl<W: Write> JsonReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Reporter for JsonReporter<W> {
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()> {
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                let line = json!({ "type": "error", "file": file, "message": e });
+                writeln!(self.out, "{line}")?;
+                return Ok(());
+            }
+        };
+        for result in &report.results {
+            let mut line = serde_json::to_value(result)?;
+            line["type"] = json!("test");
+            line["file"] = json!(file);
+            writeln!(self.out, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()> {
+        let line = json!({
+            "type": "summary",
+            "files": summary.files,
+            "ok": summary.ok,
+            "failed": summary.failed,
+            "ignored": summary.ignored,
+            "errors": summary.errors,
+            "duration": summary.duration.as_secs_f64() * 1000.0,
+        });
+        writeln!(self.out, "{line}")?;
+        Ok(())
+    }
+}
+
+/// Writes the results as JUnit XML to a file once all test files ran.
+pub(super) struct JUnitReporter {
+    path: PathBuf,
+    /// `<testsuite>` elements, one per test file.
+    suites: Vec<String>,
+}
+
+impl JUnitReporter {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            suites: vec![],
+        }
+    }
+}
+
+impl Reporter for JUnitReporter {
+    fn report_file(&mut self, file: &str, report: Result<&TestReport, &str>) -> Result<()> {
+        let name = xml_escape(file);
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                self.suites.push(format!(
+                    "  <testsuite name=\"{name}\" tests=\"1\" failures=\"0\" errors=\"1\" skipped=\"0\" time=\"0\">\n    <testcase name=\"{name}\" classname=\"{name}\" time=\"0\">\n      <error message=\"{}\">{}</error>\n    </testcase>\n  </testsuite>\n",
+                    xml_escape(e.lines().next().unwrap_or_default()),
+                    xml_escape(e),
+                ));
+                return Ok(());
+            }
+        };
+
+        let mut suite = format!(
+            "  <testsuite name=\"{name}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\">\n",
+            report.results.len(),
+            report.failed,
+            report.ignored,
+            report.duration / 1000.0
+        );
+        for result in &report.results {
+            suite.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{name}\" time=\"{:.3}\"{}",
+                xml_escape(&result.description),
+                result.duration / 1000.0,
+                location_attributes(result),
+            ));
+            match (result.status, &result.error) {
+                (TestStatus::Ok, _) => suite.push_str("/>\n"),
+                (TestStatus::Ignored, _) => {
+                    suite.push_str(">\n      <skipped/>\n    </testcase>\n")
+                }
+                (TestStatus::Failed, error) => {
+                    let (kind, message, stack) = match error {
+                        Some(e) => (e.name.as_str(), e.message.as_str(), e.stack.as_deref()),
+                        None => ("Error", "", None),
+                    };
+                    let mut details = format!("{kind}: {message}");
+                    if let Some(stack) = stack {
+                        details.push('\n');
+                        details.push_str(stack);
+                    }
+                    suite.push_str(&format!(
+                        ">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>\n",
+                        xml_escape(message),
+                        xml_escape(kind),
+                        xml_escape(&details)
+                    ));
+                }
+            }
+        }
+        suite.push_str("  </testsuite>\n");
+        self.suites.push(suite);
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &TestSummary) -> Result<()> {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"dino test\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            summary.ok + summary.failed + summary.ignored + summary.errors,
+            summary.failed,
+            summary.errors,
+            summary.ignored,
+            summary.duration.as_secs_f64()
+        );
+        for suite in &self.suites {
+            xml.push_str(suite);
+        }
+        xml.push_str("</testsuites>\n");
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, xml)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// `file` and `line` attributes from the location of a test.
+fn location_attributes(result: &TestResult) -> String {
+    let Some(location) = &result.location else {
+        return String::new();
+    };
+    // file:line:column, the file may contain colons
+    let mut parts = location.rsplitn(3, ':');
+    let (_, line, file) = (parts.next(), parts.next(), parts.next());
+    match (file, line) {
+        (Some(file), Some(line)) => format!(" file=\"{}\" line=\"{line}\"", xml_escape(file)),
+        _ => String::new(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // not allowed in XML 1.0, e.g. the escapes of coloured output
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestError;
+
+    fn report() -> TestReport {
+        TestReport {
+            ok: 1,
+            failed: 1,
+            ignored: 1,
+            duration: 12.0,
+            results: vec![
+                TestResult {
+                    description: "adds".to_string(),
+                    status: TestStatus::Ok,
+                    duration: 1.5,
+                    location: Some("main.test.ts:3:1".to_string()),
+                    error: None,
+                },
+                TestResult {
+                    description: "compares <a> & \"b\"".to_string(),
+                    status: TestStatus::Failed,
+                    duration: 2.0,
+                    location: Some("main.test.ts:7:1".to_string()),
+                    error: Some(TestError {
+                        name: "AssertionError".to_string(),
+                        message: "1 != 2".to_string(),
+                        stack: Some("    at <anonymous> (main.test.ts:8:3)".to_string()),
+                    }),
+                },
+                TestResult {
+                    description: "later".to_string(),
+                    status: TestStatus::Ignored,
+                    duration: 0.0,
+                    location: None,
+                    error: None,
+                },
+            ],
+        }
+    }
+
+    fn run(reporter: &mut dyn Reporter) -> Result<()> {
+        let summary = TestSummary {
+            files: 2,
+            ok: 1,
+            failed: 1,
+            ignored: 1,
+            errors: 1,
+            duration: Duration::from_millis(20),
+        };
+        reporter.start_file("main.test.ts")?;
+        reporter.report_file("main.test.ts", Ok(&report()))?;
+        reporter.start_file("broken.test.ts")?;
+        reporter.report_file("broken.test.ts", Err("Error: could not load module"))?;
+        reporter.finish(&summary)
+    }
+
+    #[test]
+    fn reporters_should_format_results() -> Result<()> {
+        let mut tap = TapReporter::new(vec![]);
+        run(&mut tap)?;
+        let tap = String::from_utf8(tap.out)?;
+        assert!(tap.starts_with("TAP version 13\n# main.test.ts\nok 1 - adds # time=1.500ms\n"));
+        assert!(tap.contains(
+            "not ok 2 - compares <a> & \"b\" # time=2.000ms\n  ---\n  name: \"AssertionError\"\n  message: \"1 != 2\"\n  at: \"main.test.ts:7:1\"\n  stack: \"    at <anonymous> (main.test.ts:8:3)\"\n  ...\n"
+        ));
+        assert!(tap.contains("ok 3 - later # SKIP\n# broken.test.ts\nnot ok 4 - broken.test.ts\n"));
+        assert!(tap.contains("1..4\n# ok 1\n"));
+
+        let mut json = JsonReporter::new(vec![]);
+        run(&mut json)?;
+        let lines: Vec<serde_json::Value> = String::from_utf8(json.out)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[1]["type"], "test");
+        assert_eq!(lines[1]["file"], "main.test.ts");
+        assert_eq!(lines[1]["status"], "failed");
+        assert_eq!(lines[1]["error"]["message"], "1 != 2");
+        assert_eq!(lines[3]["type"], "error");
+        assert_eq!(lines[4]["type"], "summary");
+        assert_eq!(lines[4]["errors"], 1);
+
+        let temp_dir = assert_fs::TempDir::new()?;
+        let path = temp_dir.join("reports/junit.xml");
+        run(&mut JUnitReporter::new(&path))?;
+        let xml = fs::read_to_string(path)?;
+        assert!(xml.contains("<testsuites name=\"dino test\" tests=\"4\" failures=\"1\" errors=\"1\" skipped=\"1\" time=\"0.020\">"));
+        assert!(xml.contains("<testcase name=\"adds\" classname=\"main.test.ts\" time=\"0.002\" file=\"main.test.ts\" line=\"3\"/>"));
+        assert!(xml.contains("<testcase name=\"compares &lt;a&gt; &amp; &quot;b&quot;\""));
+        assert!(xml.contains("<failure message=\"1 != 2\" type=\"AssertionError\">AssertionError: 1 != 2\n    at &lt;anonymous&gt; (main.test.ts:8:3)</failure>"));
+        assert!(xml.contains("<skipped/>"));
+        assert!(xml.contains("<error message=\"Error: could not load module\">"));
+        Ok(())
+    }
+}
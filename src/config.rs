@@ -0,0 +1,358 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use swc_ecma_ast::EsVersion;
+
+use crate::{LegalComments, MinifierOptions, OutputFormat};
+
+pub const CONFIG_FILE_NAME: &str = "config.yml";
+pub const DEFAULT_PROFILE: &str = "dev";
+const DEFAULT_ENV_FILE_NAME: &str = ".env";
+
+/// The project's `config.yml`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProjectConfig {
+    pub name: String,
+    /// Version of the project, shown in bundle banners.
+    pub version: Option<String>,
+    #[serde(default)]
+    pub route: Vec<RouteConfig>,
+    #[serde(default)]
+    pub build: BuildConfig,
+    /// Named build profiles, applied on top of `build`.
+    #[serde(default)]
+    pub profile: BTreeMap<String, BuildConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteConfig {
+    pub path: String,
+    pub method: String,
+    pub handler: String,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct BuildConfig {
+    /// Global expressions replaced at build time, e.g. `__VERSION__: '"1.0.0"'`.
+    /// Strings are JS expressions, other scalars are used as literals.
+    #[serde(default, deserialize_with = "deserialize_define")]
+    pub define: BTreeMap<String, String>,
+    /// File providing `process.env.*` values, defaults to `.env`.
+    pub env_file: Option<PathBuf>,
+    /// `false` keeps the output readable, `true` strips whitespace and runs
+    /// the minifier, which can also be configured with [`MinifierOptions`].
+    pub minify: Option<MinifyConfig>,
+    /// Write a `.js.map` next to the bundle.
+    pub source_map: Option<bool>,
+    /// ES version of the emitted code, newer syntax is compiled down to it.
+    pub target: Option<EsVersion>,
+    /// Modules bundled ahead of the entry, e.g. polyfills for older engines.
+    pub polyfills: Option<Vec<String>>,
+    /// Keep license comments `inline`, move them to an `external` file next
+    /// to the bundle, or drop them with `none`.
+    pub legal_comments: Option<LegalComments>,
+    /// Text put at the top of the bundle, see `Options::banner`.
+    pub banner: Option<String>,
+    /// Text put at the end of the bundle.
+    pub footer: Option<String>,
+    /// Module format of the bundle.
+    pub format: Option<OutputFormat>,
+    /// Write a report of the module graph next to the bundle.
+    #[serde(default)]
+    pub analyze: bool,
+    /// Number of builds kept per profile, older ones are deleted.
+    pub keep_builds: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum MinifyConfig {
+    Enabled(bool),
+    Options(MinifierOptions),
+}
+
+impl ProjectConfig {
+    /// Loads `config.yml` from the project directory, if there is one.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = path.join(CONFIG_FILE_NAME);
+        if !file.is_file() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&file)?;
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid {}", file.display()))
+    }
+
+    /// Resolves the build settings of a profile: the built-in `dev` or
+    /// `release` defaults, then `build`, then the profile's own section.
+    pub fn profile(&self, name: &str) -> Result<BuildConfig> {
+        validate_profile_name(name)?;
+        let defaults = match name {
+            "dev" => BuildConfig {
+                minify: Some(MinifyConfig::Enabled(false)),
+                source_map: Some(true),
+                ..Default::default()
+            },
+            "release" => BuildConfig {
+                minify: Some(MinifyConfig::Enabled(true)),
+                source_map: Some(false),
+                ..Default::default()
+            },
+            _ if self.profile.contains_key(name) => BuildConfig::default(),
+            _ => return Err(anyhow!("Unknown build profile \"{name}\"")),
+        };
+
+        let config = defaults.merge(&self.build);
+        Ok(match self.profile.get(name) {
+            Some(profile) => config.merge(profile),
+            None => config,
+        })
+    }
+}
+
+/// Profiles are built to `build/<name>`, so a name must be a single plain
+/// directory, and not a hidden one like `build/.cache`.
+pub fn validate_profile_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), None) if dir == name && !name.starts_with('.') => Ok(()),
+        _ => Err(anyhow!("Invalid build profile name \"{name}\"")),
+    }
+}
+
+impl MinifyConfig {
+    /// Whether whitespace is stripped from the output.
+    pub fn strip_whitespace(&self) -> bool {
+        !matches!(self, MinifyConfig::Enabled(false))
+    }
+
+    pub fn minifier(&self) -> Option<MinifierOptions> {
+        match self {
+            MinifyConfig::Enabled(enabled) => enabled.then(MinifierOptions::default),
+            MinifyConfig::Options(options) => Some(options.clone()),
+        }
+    }
+}
+
+impl BuildConfig {
+    /// Applies overrides (e.g. from CLI flags) on top of this config.
+    pub fn merge(mut self, overrides: &BuildConfig) -> Self {
+        self.define.extend(overrides.define.clone());
+        if overrides.env_file.is_some() {
+            self.env_file.clone_from(&overrides.env_file);
+        }
+        if overrides.minify.is_some() {
+            self.minify.clone_from(&overrides.minify);
+        }
+        if overrides.source_map.is_some() {
+            self.source_map = overrides.source_map;
+        }
+        if overrides.target.is_some() {
+            self.target = overrides.target;
+        }
+        if overrides.polyfills.is_some() {
+            self.polyfills.clone_from(&overrides.polyfills);
+        }
+        if overrides.legal_comments.is_some() {
+            self.legal_comments = overrides.legal_comments;
+        }
+        if overrides.banner.is_some() {
+            self.banner.clone_from(&overrides.banner);
+        }
+        if overrides.footer.is_some() {
+            self.footer.clone_from(&overrides.footer);
+        }
+        if overrides.format.is_some() {
+            self.format = overrides.format;
+        }
+        self.analyze |= overrides.analyze;
+        if overrides.keep_builds.is_some() {
+            self.keep_builds = overrides.keep_builds;
+        }
+        self
+    }
+
+    /// Collects the `define` replacements, including `process.env.*` entries
+    /// loaded from the env file. Explicit defines take precedence.
+    pub fn resolve_define(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let env_file = match &self.env_file {
+            Some(file) => path.join(file),
+            None => path.join(DEFAULT_ENV_FILE_NAME),
+        };
+
+        let mut define = BTreeMap::new();
+        if self.env_file.is_some() || env_file.is_file() {
+            let entries = dotenvy::from_path_iter(&env_file)
+                .with_context(|| format!("Failed to read {}", env_file.display()))?;
+            for entry in entries {
+                let (key, value) = entry?;
+                define.insert(
+                    format!("process.env.{key}"),
+                    serde_json::Value::String(value).to_string(),
+                );
+            }
+        }
+        define.extend(self.define.clone());
+
+        Ok(define)
+    }
+}
+
+fn deserialize_define<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let define = BTreeMap::<String, serde_yaml::Value>::deserialize(deserializer)?;
+    define
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_yaml::Value::String(value) => value,
+                serde_yaml::Value::Bool(value) => value.to_string(),
+                serde_yaml::Value::Number(value) => value.to_string(),
+                serde_yaml::Value::Null => "null".to_string(),
+                _ => {
+                    return Err(serde::de::Error::custom(format!(
+                        "define \"{key}\" must be a scalar"
+                    )))
+                }
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_config_should_parse() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: demo
+            version: 1.0.0
+            route:
+              - path: /api/hello
+                method: GET
+                handler: hello
+            build:
+              env_file: .env.production
+              define:
+                __VERSION__: '"1.0.0"'
+                __DEBUG__: false
+                __RETRIES__: 3
+              minify:
+                keep_fn_names: true
+              target: es5
+              polyfills:
+                - ./polyfills.js
+              banner: "/* {name} v{version} */"
+            "#,
+        )?;
+
+        assert_eq!(config.name, "demo");
+        assert_eq!(config.version.as_deref(), Some("1.0.0"));
+        assert_eq!(
+            config.build.banner.as_deref(),
+            Some("/* {name} v{version} */")
+        );
+        assert_eq!(config.route[0].handler, "hello");
+        assert_eq!(
+            config.build.env_file,
+            Some(PathBuf::from(".env.production"))
+        );
+        assert_eq!(config.build.define["__VERSION__"], r#""1.0.0""#);
+        assert_eq!(config.build.define["__DEBUG__"], "false");
+        assert_eq!(config.build.define["__RETRIES__"], "3");
+        assert_eq!(config.build.target, Some(EsVersion::Es5));
+        assert_eq!(
+            config.build.polyfills,
+            Some(vec!["./polyfills.js".to_string()])
+        );
+        assert_eq!(
+            config.build.minify,
+            Some(MinifyConfig::Options(MinifierOptions {
+                keep_fn_names: true,
+                ..Default::default()
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn project_config_should_resolve_profiles() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: demo
+            build:
+              target: es2020
+              define:
+                __DEBUG__: false
+            profile:
+              release:
+                source_map: true
+              staging:
+                format: esm
+                minify: true
+                define:
+                  __DEBUG__: true
+            "#,
+        )?;
+
+        let dev = config.profile("dev")?;
+        assert_eq!(dev.minify, Some(MinifyConfig::Enabled(false)));
+        assert_eq!(dev.source_map, Some(true));
+        assert_eq!(dev.target, Some(EsVersion::Es2020));
+
+        let release = config.profile("release")?;
+        assert_eq!(release.minify, Some(MinifyConfig::Enabled(true)));
+        assert_eq!(release.source_map, Some(true));
+
+        let staging = config.profile("staging")?;
+        assert_eq!(staging.format, Some(OutputFormat::Esm));
+        assert_eq!(staging.define["__DEBUG__"], "true");
+        assert_eq!(staging.source_map, None);
+
+        assert!(config.profile("unknown").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_profile_name_should_need_a_directory_name() {
+        for name in ["dev", "release", "staging-2"] {
+            assert!(validate_profile_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".", "..", ".cache", "/", "a/b", "../x", "dev/", "/tmp"] {
+            assert!(validate_profile_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn build_config_should_resolve_env_file() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join(".env"),
+            "# comment\nAPI_URL=https://example.com\nNODE_ENV=development\n",
+        )?;
+
+        let config = BuildConfig::default().merge(&BuildConfig {
+            define: BTreeMap::from([(
+                "process.env.NODE_ENV".to_string(),
+                r#""production""#.to_string(),
+            )]),
+            ..Default::default()
+        });
+        let define = config.resolve_define(&temp_dir)?;
+
+        assert_eq!(define["process.env.API_URL"], r#""https://example.com""#);
+        assert_eq!(define["process.env.NODE_ENV"], r#""production""#);
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+// A subset of the WebAssembly JS API, built on top of the native `__dino_wasm`
+// bindings (backed by the wasmi interpreter).
+((native) => {
+  class CompileError extends Error {}
+  class LinkError extends Error {}
+  class RuntimeError extends Error {}
+
+  function rethrow(ErrorClass, fn) {
+    try {
+      return fn();
+    } catch (e) {
+      throw e instanceof TypeError ? e : new ErrorClass(e.message);
+    }
+  }
+
+  // Modules and instances hold native handles, freed once collected.
+  class Module {
+    #handle;
+
+    constructor(bytes) {
+      this.#handle = rethrow(CompileError, () => native.compile(bytes));
+    }
+
+    static imports(module) {
+      return native.imports(module.#handle);
+    }
+
+    static exports(module) {
+      return native.exports(module.#handle);
+    }
+
+    static handle(module) {
+      return module.#handle;
+    }
+  }
+
+  class Memory {
+    #instance;
+    #name;
+
+    constructor(instance, name) {
+      this.#instance = instance;
+      this.#name = name;
+    }
+
+    get buffer() {
+      return native.memory(this.#instance, this.#name);
+    }
+
+    grow(delta) {
+      return native.grow(this.#instance, this.#name, delta);
+    }
+  }
+
+  class Global {
+    #instance;
+    #name;
+
+    constructor(instance, name) {
+      this.#instance = instance;
+      this.#name = name;
+    }
+
+    get value() {
+      return native.global(this.#instance, this.#name);
+    }
+
+    valueOf() {
+      return this.value;
+    }
+  }
+
+  class Instance {
+    constructor(module, importObject = {}) {
+      const values = Module.imports(module).map(({ module: ns, name, kind }) => {
+        const value = (importObject[ns] || {})[name];
+        if (value === undefined) {
+          throw new LinkError(`import ${ns}.${name} is missing`);
+        }
+        if (kind === "function") {
+          if (typeof value !== "function") {
+            throw new LinkError(`import ${ns}.${name} must be a function`);
+          }
+          return value;
+        }
+        return value instanceof Global ? value.value : value;
+      });
+
+      const handle = rethrow(LinkError, () => native.instantiate(Module.handle(module), values));
+      const exports = {};
+
+      for (const { name, kind } of Module.exports(module)) {
+        switch (kind) {
+          case "function":
+            exports[name] = (...args) => rethrow(RuntimeError, () => native.call(handle, name, ...args));
+            break;
+          case "memory":
+            exports[name] = new Memory(handle, name);
+            break;
+          case "global":
+            exports[name] = new Global(handle, name);
+            break;
+        }
+      }
+
+      this.exports = Object.freeze(exports);
+    }
+  }
+
+  globalThis.WebAssembly = {
+    Module,
+    Instance,
+    Memory,
+    Global,
+    CompileError,
+    LinkError,
+    RuntimeError,
+    validate: (bytes) => native.validate(bytes),
+    compile: async (bytes) => new Module(bytes),
+    instantiate: async (source, importObject) => {
+      if (source instanceof Module) {
+        return new Instance(source, importObject);
+      }
+      const module = new Module(source);
+      return { module, instance: new Instance(module, importObject) };
+    },
+  };
+})(globalThis.__dino_wasm);
@@ -0,0 +1,618 @@
+mod router;
+mod wasm;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    thread,
+    time::Duration,
+    time::Instant,
+};
+
+use anyhow::{anyhow, bail, Result};
+use rquickjs::{
+    function::{Constructor, This},
+    loader::{BuiltinLoader, BuiltinResolver},
+    CaughtError, Context, Ctx, FromJs, Function, IntoJs, Module, Object, Promise, Runtime, Value,
+};
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+use url::Url;
+
+use crate::{
+    CoverageCounters, OutputFormat, RouteConfig, CORE_MODULES, COVERAGE_GLOBAL, MODULES_GLOBAL,
+};
+pub use router::{RouteMatch, Router};
+
+pub struct JsWorker {
+    ctx: Context,
+}
+
+impl JsWorker {
+    pub fn try_new(module: &str) -> Result<Self> {
+        Self::try_new_with_format(module, OutputFormat::Iife)
+    }
+
+    /// Loads a bundle of the given format, whose exports become the handlers.
+    pub fn try_new_with_format(module: &str, format: OutputFormat) -> Result<Self> {
+        let rt = Runtime::new()?;
+        // bundles leave the core modules external, they are loaded from the binary
+        let mut resolver = BuiltinResolver::default();
+        let mut loader = BuiltinLoader::default();
+        for (name, source) in CORE_MODULES.iter() {
+            resolver.add_module(*name);
+            loader.add_module(*name, *source);
+        }
+        rt.set_loader(resolver, loader);
+        let ctx = Context::full(&rt)?;
+
+        ctx.with(|ctx| {
+            let global = ctx.globals();
+            // setup print function
+            let fun = Function::new(ctx.clone(), print)?.with_name("print")?;
+            global.set("print", fun)?;
+            // setup the runtime and WebAssembly before the bundle runs
+            init_runtime(&ctx).map_err(|e| js_error(&ctx, e))?;
+            wasm::init(&ctx)?;
+            let handlers = load_module(&ctx, HANDLERS_MODULE_NAME, module, format)
+                .map_err(|e| js_error(&ctx, e))?;
+            global.set("handlers", handlers)?;
+
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        Ok(Self { ctx })
+    }
+
+    /// Loads another bundle next to the handlers, e.g. a test file. Its
+    /// exports are kept in `MODULES_GLOBAL` under `name`.
+    pub fn load(&self, name: &str, module: &str, format: OutputFormat) -> Result<()> {
+        self.ctx.with(|ctx| {
+            let exports = load_module(&ctx, name, module, format).map_err(|e| js_error(&ctx, e))?;
+            let global = ctx.globals();
+            let modules = match global.get::<_, Option<Object>>(MODULES_GLOBAL)? {
+                Some(modules) => modules,
+                None => {
+                    let modules = Object::new(ctx.clone())?;
+                    global.set(MODULES_GLOBAL, modules.clone())?;
+                    modules
+                }
+            };
+            modules.set(name, exports)?;
+            Ok(())
+        })
+    }
+
+    /// Routes requests made from JS, e.g. with the `test` module's
+    /// `request()`, to the handlers through these routes.
+    pub fn set_routes(&self, routes: &[RouteConfig]) -> Result<()> {
+        let router = Router::new(routes);
+        self.ctx.with(|ctx| {
+            let route = Function::new(ctx.clone(), move |ctx, method: String, url: String| {
+                route_request(ctx, &router, &method, &url)
+            })?;
+            ctx.globals().set(ROUTE_GLOBAL, route.with_name("route")?)?;
+            Ok(())
+        })
+    }
+
+    #[allow(unused)]
+    pub fn run(&self, code: &str) -> anyhow::Result<()> {
+        self.ctx.with(|ctx| {
+            let promise = ctx.eval_promise(code).map_err(|e| js_error(&ctx, e))?;
+            settle::<Value>(&ctx, promise)?;
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        Ok(())
+    }
+
+    /// Evaluates a script, e.g. from `compile_script`, waiting for its
+    /// top-level await. Returns its completion value formatted for a REPL,
+    /// or `None` when it is `undefined`.
+    pub fn eval(&self, code: &str) -> Result<Option<String>> {
+        self.ctx.with(|ctx| {
+            let promise = ctx.eval_promise(code).map_err(|e| js_error(&ctx, e))?;
+            // scripts with top-level await resolve to `{ value }`
+            let completion: Object = settle(&ctx, promise)?;
+            let value: Value = completion.get("value")?;
+            if value.is_undefined() {
+                return Ok(None);
+            }
+            let inspect: Function = ctx.eval(INSPECT).map_err(|e| js_error(&ctx, e))?;
+            let text: String = inspect.call((value,)).map_err(|e| js_error(&ctx, e))?;
+            Ok(Some(text))
+        })
+    }
+
+    /// Names of the properties, own and inherited, of what `expr` evaluates
+    /// to, e.g. for completion. Empty when it can't be evaluated.
+    pub fn property_names(&self, expr: &str) -> Vec<String> {
+        self.ctx.with(|ctx| {
+            let names = |ctx: &Ctx| -> rquickjs::Result<Vec<String>> {
+                let value: Value = ctx.eval(expr)?;
+                let names: Function = ctx.eval(PROPERTY_NAMES)?;
+                names.call((value,))
+            };
+            let names = names(&ctx).unwrap_or_default();
+            // don't leave the exception of a failed evaluation behind
+            let _ = ctx.catch();
+            names
+        })
+    }
+
+    pub fn run_http(&self, name: &str, req: Req) -> anyhow::Result<Res> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let handlers: Object = global.get("handlers")?;
+            let fun: Function = handlers.get(name)?;
+            let v: Promise = fun.call((req,)).map_err(|e| js_error(&ctx, e))?;
+            settle(&ctx, v)
+        })
+    }
+
+    /// Runs the tests registered with the `test` module's runner, optionally
+    /// only those whose description matches `filter`, a regular expression.
+    pub fn run_tests(&self, filter: Option<&str>, fail_fast: bool) -> Result<TestReport> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let Some(runner) = global.get::<_, Option<Object>>(TEST_RUNNER_GLOBAL)? else {
+                return Ok(TestReport::default());
+            };
+            if let Some(filter) = filter {
+                let regexp: Constructor = global.get("RegExp")?;
+                let filter: Value = regexp.construct((filter,)).map_err(|e| js_error(&ctx, e))?;
+                runner.set("filter", filter)?;
+            }
+            runner.set("failFast", fail_fast)?;
+            // the results are reported by the caller
+            runner.set("silent", true)?;
+
+            let run: Function = runner.get("run")?;
+            let promise: Promise = run.call((This(runner),)).map_err(|e| js_error(&ctx, e))?;
+            let report: Value = settle(&ctx, promise)?;
+            let json = ctx
+                .json_stringify(report)?
+                .ok_or_else(|| anyhow!("The test runner didn't return a report"))?;
+            Ok(serde_json::from_str(&json.to_string()?)?)
+        })
+    }
+
+    /// Counters of the instrumented modules run so far, by file. Empty
+    /// unless the code was bundled with `Options::coverage`.
+    pub fn coverage(&self) -> Result<BTreeMap<String, CoverageCounters>> {
+        self.ctx.with(|ctx| {
+            let Some(counters) = ctx.globals().get::<_, Option<Value>>(COVERAGE_GLOBAL)? else {
+                return Ok(BTreeMap::new());
+            };
+            match ctx.json_stringify(counters)? {
+                Some(json) => Ok(serde_json::from_str(&json.to_string()?)?),
+                None => Ok(BTreeMap::new()),
+            }
+        })
+    }
+}
+
+/// Name the handlers' module is loaded under, as seen in stacks.
+pub const HANDLERS_MODULE_NAME: &str = "main";
+
+/// Global through which JS resolves a request to its handler, see
+/// [`JsWorker::set_routes`].
+const ROUTE_GLOBAL: &str = "$$route";
+/// Base of the URLs given as a path.
+const BASE_URL: &str = "http://localhost";
+
+/// Formats a value the way a REPL shows it.
+const INSPECT: &str = r#"(value) => {
+  if (typeof value === 'string') return JSON.stringify(value);
+  if (typeof value === 'function') return `[Function: ${value.name || '(anonymous)'}]`;
+  if (typeof value === 'bigint') return `${value}n`;
+  if (value instanceof Error) return value.stack ? `${value}\n${value.stack}` : `${value}`;
+  if (typeof value === 'object' && value !== null) {
+    try {
+      const json = JSON.stringify(value, null, 2);
+      if (json !== undefined) return json;
+    } catch {}
+  }
+  return String(value);
+}"#;
+
+/// Lists the property names of a value and its prototypes.
+const PROPERTY_NAMES: &str = r#"(value) => {
+  const names = new Set();
+  if (value === null || value === undefined) return [];
+  for (let object = Object(value); object !== null; object = Object.getPrototypeOf(object)) {
+    for (const name of Object.getOwnPropertyNames(object)) names.add(name);
+  }
+  return [...names];
+}"#;
+
+/// Global through which the `test` module exposes its runner.
+const TEST_RUNNER_GLOBAL: &str = "$$mainRunner";
+
+/// Results of [`JsWorker::run_tests`].
+#[derive(Debug, Default, Deserialize)]
+pub struct TestReport {
+    pub ok: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    /// Milliseconds taken by the whole run.
+    pub duration: f64,
+    pub results: Vec<TestResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestResult {
+    pub description: String,
+    pub status: TestStatus,
+    /// Milliseconds taken by the test, zero when ignored.
+    pub duration: f64,
+    /// Where the test is declared, as `file:line:column`.
+    pub location: Option<String>,
+    /// The error failing the test.
+    pub error: Option<TestError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestError {
+    /// Name of the error's class, e.g. `AssertionError`.
+    pub name: String,
+    pub message: String,
+    /// Frames of the stack, one `    at ...` line each.
+    pub stack: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Ok,
+    Failed,
+    Ignored,
+}
+
+#[derive(Debug, TypedBuilder)]
+pub struct Req {
+    pub headers: HashMap<String, String>,
+    #[builder(setter(into))]
+    pub method: String,
+    #[builder(setter(into))]
+    pub url: String,
+    /// Values of the route's `{name}` path segments.
+    #[builder(default)]
+    pub params: HashMap<String, String>,
+    #[builder(default, setter(strip_option))]
+    pub body: Option<String>,
+}
+
+impl<'js> IntoJs<'js> for Req {
+    fn into_js(self, ctx: &rquickjs::Ctx<'js>) -> rquickjs::Result<rquickjs::Value<'js>> {
+        let obj = Object::new(ctx.clone())?;
+
+        obj.set("header", self.headers)?;
+        obj.set("method", self.method)?;
+        obj.set("url", self.url)?;
+        obj.set("params", self.params)?;
+        obj.set("body", self.body)?;
+
+        Ok(obj.into())
+    }
+}
+
+#[allow(unused)]
+#[derive(Debug, TypedBuilder)]
+pub struct Res {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Option<String>,
+}
+
+impl<'js> FromJs<'js> for Res {
+    fn from_js(_ctx: &rquickjs::Ctx<'js>, value: rquickjs::Value<'js>) -> rquickjs::Result<Self> {
+        let obj = value.into_object().unwrap();
+
+        let status = obj.get("status")?;
+        let headers = obj.get("headers")?;
+        let body = obj.get("body")?;
+
+        Ok(Res {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+fn load_module<'js>(
+    ctx: &Ctx<'js>,
+    name: &str,
+    module: &str,
+    format: OutputFormat,
+) -> rquickjs::Result<Object<'js>> {
+    let handlers = match format {
+        OutputFormat::Iife => ctx.eval(module)?,
+        OutputFormat::Esm => {
+            let (module, promise) = Module::declare(ctx.clone(), name, module)?.eval()?;
+            // wait for top-level await
+            promise.finish::<()>()?;
+            module.namespace()?
+        }
+        OutputFormat::Cjs => {
+            let wrapper: Function =
+                ctx.eval(format!("(function(module, exports) {{\n{module}\n}})"))?;
+            let exports = Object::new(ctx.clone())?;
+            let cjs_module = Object::new(ctx.clone())?;
+            cjs_module.set("exports", exports.clone())?;
+            wrapper.call::<_, ()>((cjs_module.clone(), exports))?;
+            cjs_module.get("exports")?
+        }
+    };
+    Ok(handlers)
+}
+
+/// Resolves a request to `{ url, handler, params }`, or `{ url, status }`
+/// when no route matches.
+fn route_request<'js>(
+    ctx: Ctx<'js>,
+    router: &Router,
+    method: &str,
+    url: &str,
+) -> rquickjs::Result<Object<'js>> {
+    let url = Url::parse(BASE_URL)
+        .and_then(|base| base.join(url))
+        .map_err(|e| rquickjs::Exception::throw_type(&ctx, &format!("Invalid URL {url}: {e}")))?;
+    let obj = Object::new(ctx)?;
+    obj.set("url", url.as_str())?;
+    match router.find(method, url.path()) {
+        RouteMatch::Found { handler, params } => {
+            obj.set("handler", handler)?;
+            obj.set("params", params)?;
+        }
+        RouteMatch::MethodNotAllowed => obj.set("status", 405)?,
+        RouteMatch::NotFound => obj.set("status", 404)?,
+    }
+    Ok(obj)
+}
+
+/// Installs the runtime globals: console, timers, performance and `process`.
+fn init_runtime(ctx: &Ctx) -> rquickjs::Result<()> {
+    let global = ctx.globals();
+    let eprint = Function::new(ctx.clone(), |msg: String| eprintln!("{msg}"))?;
+    global.set("$$eprint", eprint.with_name("eprint")?)?;
+    let started = Instant::now();
+    let now = Function::new(ctx.clone(), move || {
+        started.elapsed().as_secs_f64() * 1000.0
+    })?;
+    global.set("$$now", now.with_name("now")?)?;
+    ctx.eval::<(), _>(include_str!("runtime.js"))
+}
+
+/// Runs the event loop until the promise settles: pending jobs first, then
+/// timers, sleeping until the next one is due.
+fn settle<'js, T: FromJs<'js>>(ctx: &Ctx<'js>, promise: Promise<'js>) -> Result<T> {
+    let timers: Object = ctx.globals().get("$$timers")?;
+    let next: Function = timers.get("next")?;
+    let fire: Function = timers.get("fire")?;
+    let now: Function = ctx.globals().get("$$now")?;
+    loop {
+        while ctx.execute_pending_job() {}
+        if let Some(result) = promise.result::<T>() {
+            return result.map_err(|e| js_error(ctx, e));
+        }
+
+        let Some(due) = next.call::<_, Option<f64>>((This(timers.clone()),))? else {
+            bail!("The promise can't settle: no jobs or timers are pending");
+        };
+        let wait = due - now.call::<_, f64>(())?;
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait / 1000.0));
+        }
+        fire.call::<_, ()>((This(timers.clone()),))
+            .map_err(|e| js_error(ctx, e))?;
+    }
+}
+
+/// Turns JS exceptions into errors carrying their message and stack.
+fn js_error(ctx: &Ctx, error: rquickjs::Error) -> anyhow::Error {
+    if !error.is_exception() {
+        return error.into();
+    }
+    match CaughtError::from_error(ctx, error) {
+        CaughtError::Exception(e) => anyhow!("{e}"),
+        CaughtError::Value(v) => anyhow!("Uncaught {v:?}"),
+        CaughtError::Error(e) => e.into(),
+    }
+}
+
+fn print(msg: String) {
+    println!("{msg}");
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{collections::HashMap, fs};
+
+    use crate::run_bundle;
+
+    use super::*;
+
+    #[test]
+    fn js_worker_should_run() {
+        let code = r#"
+    (function(){async function hello(){print("hello world");return"hello";}return{hello:hello};})();
+    "#;
+        let worker = JsWorker::try_new(code).unwrap();
+        worker.run("await handlers.hello()").unwrap();
+    }
+
+    #[test]
+    fn js_worker_should_eval_scripts() -> Result<()> {
+        let worker = JsWorker::try_new_with_format("", OutputFormat::Esm)?;
+        worker.load(
+            "./lib.ts",
+            "export const greet = (name) => `hi ${name}`;",
+            OutputFormat::Esm,
+        )?;
+        assert_eq!(worker.eval("let x = 20; x + 1")?.as_deref(), Some("21"));
+        // declarations stay, and top-level await settles timers
+        assert_eq!(
+            worker
+                .eval("await new Promise((resolve) => setTimeout(() => resolve(x * 2), 10))")?
+                .as_deref(),
+            Some("40")
+        );
+        assert_eq!(
+            worker
+                .eval(r#"globalThis.$$modules["./lib.ts"].greet("dino")"#)?
+                .as_deref(),
+            Some(r#""hi dino""#)
+        );
+        assert_eq!(worker.eval("var y = 1")?, None);
+        assert!(worker.eval("missing()").is_err());
+
+        let names = worker.property_names("globalThis");
+        assert!(names.iter().any(|name| name == "setTimeout"), "{names:?}");
+        assert!(worker
+            .property_names("'abc'")
+            .contains(&"toUpperCase".to_string()));
+        assert!(worker.property_names("missing.value").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn js_worker_should_run_http() {
+        let code = r#"
+            (function(){
+                async function hello(req){
+                    return {
+                        status:200,
+                        headers:{
+                            "content-type":"application/json"
+                        },
+                        body: JSON.stringify(req),
+                    };
+                }
+                return{hello:hello};
+            })();
+        "#;
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com")
+            .headers(HashMap::new())
+            .build();
+        let worker = JsWorker::try_new(code).unwrap();
+        worker.run_http("hello", req).unwrap();
+    }
+
+    #[test]
+    fn js_worker_should_run_wasm() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "offset" (func $offset (result i32)))
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add
+                    call $offset
+                    i32.add))
+            "#,
+        )?;
+        fs::write(temp_dir.join("lib.wasm"), wasm)?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            import init from "./lib.wasm";
+
+            async function add(req: any) {
+                const instance = await init({ env: { offset: () => 100 } });
+                return {
+                    status: 200,
+                    headers: {},
+                    body: String(instance.exports.add(1, 2)),
+                };
+            }
+
+            export { add };
+            "#,
+        )?;
+
+        let bundle = run_bundle(
+            &temp_dir.join("main.ts").display().to_string(),
+            &Default::default(),
+        )?;
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com")
+            .headers(HashMap::new())
+            .build();
+        let worker = JsWorker::try_new(&bundle)?;
+        let res = worker.run_http("add", req)?;
+        assert_eq!(res.body.as_deref(), Some("103"));
+        Ok(())
+    }
+
+    #[test]
+    fn js_worker_should_share_wasm_memory() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "greet")
+                    (i32.store8 (i32.const 0) (i32.const 104))
+                    (i32.store8 (i32.const 1) (i32.const 105))
+                    (call $log (i32.const 0) (i32.const 2)))
+                (func (export "double") (param i32) (result i32)
+                    (i32.mul (local.get 0) (i32.const 2)))
+                (func (export "sum") (param $ptr i32) (param $len i32) (result i32)
+                    (local $total i32)
+                    (block $done
+                        (loop $next
+                            (br_if $done (i32.eqz (local.get $len)))
+                            (local.set $total
+                                (i32.add (local.get $total) (i32.load8_u (local.get $ptr))))
+                            (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                            (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                            (br $next)))
+                    (local.get $total))
+                (func (export "grow") (result i32)
+                    (memory.grow (i32.const 1))))
+            "#,
+        )?;
+        let worker = JsWorker::try_new_with_format("", OutputFormat::Esm)?;
+        let script = format!(
+            r#"
+            let instance, logged;
+            const log = (ptr, len) => {{
+                // imports can read the memory and call back into the instance
+                const bytes = new Uint8Array(instance.exports.memory.buffer, ptr, len);
+                logged = String.fromCharCode(...bytes) + instance.exports.double(len);
+            }};
+            ({{ instance }} = await WebAssembly.instantiate(
+                new Uint8Array({wasm:?}),
+                {{ env: {{ log }} }},
+            ));
+            const {{ memory, greet, sum, grow }} = instance.exports;
+            const buffer = memory.buffer;
+            new Uint8Array(buffer).set([1, 2, 3], 8);
+            greet();
+            [
+                sum(8, 3),
+                logged,
+                new Uint8Array(buffer)[1],
+                memory.buffer === buffer,
+                grow(),
+                buffer.byteLength,
+                memory.buffer.byteLength,
+            ].join()
+            "#
+        );
+        assert_eq!(
+            worker.eval(&script)?.as_deref(),
+            Some(r#""6,hi4,105,true,1,0,131072""#)
+        );
+        Ok(())
+    }
+}
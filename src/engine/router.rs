@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use percent_encoding::percent_decode_str;
+
+use crate::RouteConfig;
+
+/// Matches requests against the routes of config.yml. A path segment like
+/// `{id}` matches any segment and is passed to the handler as a param.
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    method: String,
+    segments: Vec<Segment>,
+    handler: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RouteMatch<'a> {
+    Found {
+        handler: &'a str,
+        params: HashMap<String, String>,
+    },
+    /// The path has routes, but none for this method.
+    MethodNotAllowed,
+    NotFound,
+}
+
+impl Router {
+    pub fn new(routes: &[RouteConfig]) -> Self {
+        let routes = routes
+            .iter()
+            .map(|route| Route {
+                method: route.method.to_uppercase(),
+                segments: split_path(&route.path)
+                    .map(|segment| {
+                        match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                            Some(name) => Segment::Param(name.to_string()),
+                            None => Segment::Static(segment.to_string()),
+                        }
+                    })
+                    .collect(),
+                handler: route.handler.clone(),
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Finds the route for a request. Routes with fewer params win, e.g.
+    /// `/users/me` over `/users/{id}`, otherwise the first one in config.yml.
+    pub fn find(&self, method: &str, path: &str) -> RouteMatch<'_> {
+        let segments: Vec<_> = split_path(path).collect();
+        let mut path_found = false;
+        let mut best: Option<(&Route, HashMap<String, String>)> = None;
+        for route in &self.routes {
+            let Some(params) = route.matches(&segments) else {
+                continue;
+            };
+            path_found = true;
+            if !route.method.eq_ignore_ascii_case(method) {
+                continue;
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| params.len() < best.len())
+            {
+                best = Some((route, params));
+            }
+        }
+        match best {
+            Some((route, params)) => RouteMatch::Found {
+                handler: &route.handler,
+                params,
+            },
+            None if path_found => RouteMatch::MethodNotAllowed,
+            None => RouteMatch::NotFound,
+        }
+    }
+}
+
+impl Route {
+    fn matches(&self, segments: &[&str]) -> Option<HashMap<String, String>> {
+        if segments.len() != self.segments.len() {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (segment, expected) in segments.iter().zip(&self.segments) {
+            match expected {
+                Segment::Static(s) if s == segment => {}
+                Segment::Param(name) => {
+                    let value = percent_decode_str(segment).decode_utf8_lossy();
+                    params.insert(name.clone(), value.to_string());
+                }
+                Segment::Static(_) => return None,
+            }
+        }
+        Some(params)
+    }
+}
+
+/// Splits a path into its segments, ignoring empty ones so that a trailing
+/// slash doesn't matter.
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(method: &str, path: &str, handler: &str) -> RouteConfig {
+        RouteConfig {
+            path: path.to_string(),
+            method: method.to_string(),
+            handler: handler.to_string(),
+        }
+    }
+
+    #[test]
+    fn router_should_find_routes() {
+        let router = Router::new(&[
+            route("GET", "/", "index"),
+            route("GET", "/api/users/{id}", "getUser"),
+            route("GET", "/api/users/me", "getMe"),
+            route("post", "/api/users", "createUser"),
+        ]);
+
+        assert_eq!(
+            router.find("GET", "/"),
+            RouteMatch::Found {
+                handler: "index",
+                params: HashMap::new()
+            }
+        );
+        assert_eq!(
+            router.find("get", "/api/users/john%20doe/"),
+            RouteMatch::Found {
+                handler: "getUser",
+                params: HashMap::from([("id".to_string(), "john doe".to_string())])
+            }
+        );
+        assert_eq!(
+            router.find("GET", "/api/users/me"),
+            RouteMatch::Found {
+                handler: "getMe",
+                params: HashMap::new()
+            }
+        );
+        assert!(matches!(
+            router.find("POST", "/api/users"),
+            RouteMatch::Found {
+                handler: "createUser",
+                ..
+            }
+        ));
+        assert_eq!(
+            router.find("DELETE", "/api/users"),
+            RouteMatch::MethodNotAllowed
+        );
+        assert_eq!(router.find("GET", "/api/posts"), RouteMatch::NotFound);
+    }
+}
@@ -0,0 +1,107 @@
+// Worker runtime: console, timers, performance and a minimal `process`,
+// installed before the bundle runs. Timers are kept here and fired by the
+// worker's event loop through `$$timers`.
+(() => {
+  const { print, $$eprint, $$now } = globalThis;
+
+  function format(args) {
+    return args
+      .map((arg) => {
+        if (typeof arg === 'string') return arg;
+        if (arg instanceof Error) return arg.stack ? `${arg}\n${arg.stack}` : `${arg}`;
+        try {
+          return JSON.stringify(arg) ?? String(arg);
+        } catch {
+          return String(arg);
+        }
+      })
+      .join(' ');
+  }
+
+  globalThis.console = {
+    log: (...args) => print(format(args)),
+    info: (...args) => print(format(args)),
+    debug: (...args) => print(format(args)),
+    warn: (...args) => $$eprint(format(args)),
+    error: (...args) => $$eprint(format(args)),
+  };
+
+  const timers = new Map();
+  let nextId = 1;
+
+  function schedule(callback, delay, args, repeat) {
+    if (typeof callback !== 'function') {
+      throw new TypeError(`The "callback" argument must be of type function.`);
+    }
+    // Intervals wait at least 1ms, so that they can't starve the event loop.
+    delay = Math.max(repeat ? 1 : 0, Number(delay) || 0);
+    const id = nextId++;
+    timers.set(id, { callback, args, delay, repeat, due: $$now() + delay });
+    return id;
+  }
+
+  function clear(id) {
+    timers.delete(id);
+  }
+
+  globalThis.setTimeout = (callback, delay, ...args) => schedule(callback, delay, args, false);
+  globalThis.setInterval = (callback, delay, ...args) => schedule(callback, delay, args, true);
+  globalThis.clearTimeout = clear;
+  globalThis.clearInterval = clear;
+
+  globalThis.$$timers = {
+    // When the next timer is due, in `performance.now()` time.
+    next() {
+      let next;
+      for (const timer of timers.values()) {
+        if (next === undefined || timer.due < next) next = timer.due;
+      }
+      return next;
+    },
+    // Runs the timers that are due, earliest first.
+    fire() {
+      const now = $$now();
+      const due = [...timers.entries()]
+        .filter(([, timer]) => timer.due <= now)
+        .sort(([a, x], [b, y]) => x.due - y.due || a - b);
+
+      for (const [id, timer] of due) {
+        // An earlier callback may have cleared it.
+        if (!timers.has(id)) continue;
+        if (timer.repeat) {
+          timer.due = now + timer.delay;
+        } else {
+          timers.delete(id);
+        }
+        try {
+          timer.callback(...timer.args);
+        } catch (err) {
+          console.error('Uncaught', err);
+        }
+      }
+    },
+  };
+
+  const performance = { now: $$now, timeOrigin: Date.now() - $$now() };
+  globalThis.performance = performance;
+
+  const bindings = { perf_hooks: { performance } };
+  // Bindings of the core modules that workers don't provide fail on use.
+  function unavailable(name) {
+    return new Proxy(
+      {},
+      {
+        get(_, key) {
+          if (typeof key === 'symbol') return undefined;
+          throw new Error(`process.binding('${name}').${key} is not available in workers`);
+        },
+      }
+    );
+  }
+
+  globalThis.process = {
+    argv: [],
+    env: {},
+    binding: (name) => bindings[name] ?? unavailable(name),
+  };
+})();
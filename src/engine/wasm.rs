@@ -0,0 +1,653 @@
+use std::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    mem,
+    ptr::NonNull,
+    rc::{Rc, Weak},
+};
+
+use rquickjs::{
+    class::Trace, prelude::Rest, qjs, Array, ArrayBuffer, BigInt, Class, Ctx, Exception, Function,
+    Object, Persistent, TypedArray, Value,
+};
+use wasmi::{
+    AsContextMut, Caller, Engine, Extern, ExternType, Global, Instance, Linker, Memory, Module,
+    Mutability, Store, StoreContextMut, Val, ValType, F32, F64,
+};
+
+/// Name of the hidden global the `WebAssembly` shim is built on.
+const NATIVE: &str = "__dino_wasm";
+
+/// A compiled module, wrapped by `WebAssembly.Module`.
+#[derive(Trace)]
+#[rquickjs::class(rename = "WasmModule")]
+struct WasmModule {
+    #[qjs(skip_trace)]
+    module: Module,
+}
+
+/// A live instance, wrapped by `WebAssembly.Instance`. Each instance has a
+/// store of its own, freed once the wrapper is collected.
+#[derive(Trace)]
+#[rquickjs::class(rename = "WasmInstance")]
+struct WasmInstance<'js> {
+    #[qjs(skip_trace)]
+    instance: Instance,
+    #[qjs(skip_trace)]
+    store: Rc<InstanceStore>,
+    /// The import values, in module order.
+    imports: Array<'js>,
+}
+
+struct InstanceStore {
+    slot: RefCell<StoreSlot>,
+    /// Buffers handed out for the memories, see `memory_buffer`.
+    buffers: RefCell<Vec<LiveBuffer>>,
+    next_buffer: Cell<u64>,
+}
+
+/// The store is moved out while wasm code runs, so that imports can call
+/// back into the instance through their caller.
+enum StoreSlot {
+    Idle(Box<Store<HostState>>),
+    /// Points at the caller of the running import, `None` while wasm code runs.
+    Lent(Option<NonNull<Caller<'static, HostState>>>),
+}
+
+/// Store data, lets imported functions call back into the JS context.
+struct HostState {
+    ctx: NonNull<qjs::JSContext>,
+    store: Weak<InstanceStore>,
+    /// The import values while a call runs.
+    imports: Option<Persistent<Array<'static>>>,
+}
+
+/// An `ArrayBuffer` over the data of a memory. It is not owned: the buffer
+/// removes itself when collected, see `free_buffer`.
+struct LiveBuffer {
+    id: u64,
+    name: String,
+    memory: Memory,
+    data: (*mut u8, usize),
+    object: qjs::JSValue,
+    owner: NonNull<BufferOwner>,
+}
+
+/// Keeps the store of a buffer's memory alive while the buffer is.
+struct BufferOwner {
+    store: Rc<InstanceStore>,
+    id: u64,
+    /// Set while `sync_buffers` detaches the buffer, the finalizer frees it.
+    detaching: Cell<bool>,
+}
+
+/// Installs a `WebAssembly` global backed by the wasmi interpreter.
+pub fn init<'js>(ctx: &Ctx<'js>) -> rquickjs::Result<()> {
+    let engine = Engine::default();
+    let native = Object::new(ctx.clone())?;
+
+    native.set(
+        "validate",
+        Function::new(ctx.clone(), {
+            let engine = engine.clone();
+            move |bytes: Value<'js>| -> rquickjs::Result<bool> {
+                Ok(Module::new(&engine, &to_bytes(&bytes)?).is_ok())
+            }
+        })?,
+    )?;
+
+    native.set(
+        "compile",
+        Function::new(ctx.clone(), {
+            let engine = engine.clone();
+            move |ctx: Ctx<'js>, bytes: Value<'js>| -> rquickjs::Result<Class<'js, WasmModule>> {
+                let module = Module::new(&engine, &to_bytes(&bytes)?)
+                    .map_err(|e| Exception::throw_message(&ctx, &e.to_string()))?;
+                Class::instance(ctx, WasmModule { module })
+            }
+        })?,
+    )?;
+
+    native.set(
+        "imports",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>, module: Class<'js, WasmModule>| -> rquickjs::Result<Vec<Object<'js>>> {
+                let module = module.borrow();
+                module
+                    .module
+                    .imports()
+                    .map(|import| {
+                        let obj = Object::new(ctx.clone())?;
+                        obj.set("module", import.module())?;
+                        obj.set("name", import.name())?;
+                        obj.set("kind", kind(import.ty()))?;
+                        Ok(obj)
+                    })
+                    .collect()
+            },
+        )?,
+    )?;
+
+    native.set(
+        "exports",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>, module: Class<'js, WasmModule>| -> rquickjs::Result<Vec<Object<'js>>> {
+                let module = module.borrow();
+                module
+                    .module
+                    .exports()
+                    .map(|export| {
+                        let obj = Object::new(ctx.clone())?;
+                        obj.set("name", export.name())?;
+                        obj.set("kind", kind(export.ty()))?;
+                        Ok(obj)
+                    })
+                    .collect()
+            },
+        )?,
+    )?;
+
+    // Imports are passed in module order, functions are called back from
+    // `call_import`.
+    native.set(
+        "instantiate",
+        Function::new(ctx.clone(), {
+            let engine = engine.clone();
+            move |ctx: Ctx<'js>,
+                  module: Class<'js, WasmModule>,
+                  imports: Array<'js>|
+                  -> rquickjs::Result<Class<'js, WasmInstance<'js>>> {
+                let module = module.borrow().module.clone();
+                instantiate(&ctx, &engine, &module, imports)
+            }
+        })?,
+    )?;
+
+    native.set(
+        "call",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>,
+             instance: Class<'js, WasmInstance<'js>>,
+             name: String,
+             args: Rest<Value<'js>>| { call_export(&ctx, &instance, &name, args.0) },
+        )?,
+    )?;
+
+    native.set(
+        "global",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>,
+             instance: Class<'js, WasmInstance<'js>>,
+             name: String|
+             -> rquickjs::Result<Value<'js>> {
+                let (instance, store) = handles(&instance);
+                let val = with_store(&ctx, &store, |store| {
+                    match instance
+                        .get_export(&store, &name)
+                        .and_then(Extern::into_global)
+                    {
+                        Some(global) => Ok(global.get(&store)),
+                        None => Err(Exception::throw_type(
+                            &ctx,
+                            &format!("{name} is not a global"),
+                        )),
+                    }
+                })?;
+                from_val(&ctx, val)
+            },
+        )?,
+    )?;
+
+    native.set(
+        "memory",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>,
+             instance: Class<'js, WasmInstance<'js>>,
+             name: String|
+             -> rquickjs::Result<Value<'js>> {
+                let (instance, store) = handles(&instance);
+                memory_buffer(&ctx, instance, &store, &name)
+            },
+        )?,
+    )?;
+
+    native.set(
+        "grow",
+        Function::new(
+            ctx.clone(),
+            |ctx: Ctx<'js>,
+             instance: Class<'js, WasmInstance<'js>>,
+             name: String,
+             delta: u64|
+             -> rquickjs::Result<u64> {
+                let (instance, store) = handles(&instance);
+                let pages = with_store(&ctx, &store, |mut store| {
+                    let memory = get_memory(&ctx, instance, &store, &name)?;
+                    memory
+                        .grow(&mut store, delta)
+                        .map_err(|e| Exception::throw_range(&ctx, &e.to_string()))
+                })?;
+                sync_buffers(&ctx, &store)?;
+                Ok(pages)
+            },
+        )?,
+    )?;
+
+    ctx.globals().set(NATIVE, native)?;
+    ctx.eval::<(), _>(include_str!("./js/wasm.js"))?;
+
+    Ok(())
+}
+
+fn instantiate<'js>(
+    ctx: &Ctx<'js>,
+    engine: &Engine,
+    module: &Module,
+    imports: Array<'js>,
+) -> rquickjs::Result<Class<'js, WasmInstance<'js>>> {
+    let store = Rc::new_cyclic(|weak| InstanceStore {
+        slot: RefCell::new(StoreSlot::Idle(Box::new(Store::new(
+            engine,
+            HostState {
+                ctx: ctx.as_raw(),
+                store: weak.clone(),
+                imports: None,
+            },
+        )))),
+        buffers: RefCell::new(vec![]),
+        next_buffer: Cell::new(0),
+    });
+
+    let mut linker = Linker::<HostState>::new(engine);
+    let instance = with_store(ctx, &store, |mut store| {
+        for (i, import) in module.imports().enumerate() {
+            let value: Value = imports.get(i)?;
+            let (module_name, name) = (import.module(), import.name());
+            let link_error = |e: &dyn std::fmt::Display| {
+                Exception::throw_message(ctx, &format!("{module_name}.{name}: {e}"))
+            };
+
+            match import.ty() {
+                ExternType::Func(ty) => {
+                    let ty = ty.clone();
+                    linker
+                        .func_new(
+                            module_name,
+                            name,
+                            ty.clone(),
+                            move |caller, params, results| {
+                                call_import(caller, i, &ty, params, results)
+                            },
+                        )
+                        .map_err(|e| link_error(&e))?;
+                }
+                ExternType::Global(ty) => {
+                    let val = to_val(ctx, value, ty.content())?;
+                    let global = Global::new(&mut store, val, Mutability::Const);
+                    linker
+                        .define(module_name, name, global)
+                        .map_err(|e| link_error(&e))?;
+                }
+                _ => {
+                    return Err(link_error(
+                        &"only function and global imports are supported",
+                    ))
+                }
+            }
+        }
+
+        // the start function may call imports
+        let previous = store
+            .data_mut()
+            .imports
+            .replace(Persistent::save(ctx, imports.clone()));
+        let instance = linker.instantiate_and_start(&mut store, module);
+        store.data_mut().imports = previous;
+        instance.map_err(|e| Exception::throw_message(ctx, &e.to_string()))
+    })?;
+
+    Class::instance(
+        ctx.clone(),
+        WasmInstance {
+            instance,
+            store,
+            imports,
+        },
+    )
+}
+
+/// Copies the handles out, so that the class isn't borrowed while wasm
+/// code runs.
+fn handles<'js>(instance: &Class<'js, WasmInstance<'js>>) -> (Instance, Rc<InstanceStore>) {
+    let instance = instance.borrow();
+    (instance.instance, instance.store.clone())
+}
+
+/// Runs `f` with the store of an instance: the idle one, or the one of the
+/// running import's caller when the import calls back into the instance.
+/// The slot isn't borrowed while `f` runs.
+fn with_store<R>(
+    ctx: &Ctx<'_>,
+    store: &InstanceStore,
+    f: impl FnOnce(StoreContextMut<'_, HostState>) -> rquickjs::Result<R>,
+) -> rquickjs::Result<R> {
+    let mut slot = store.slot.borrow_mut();
+    match mem::replace(&mut *slot, StoreSlot::Lent(None)) {
+        StoreSlot::Idle(mut idle) => {
+            drop(slot);
+            let ret = f(idle.as_context_mut());
+            *store.slot.borrow_mut() = StoreSlot::Idle(idle);
+            ret
+        }
+        StoreSlot::Lent(Some(mut caller)) => {
+            *slot = StoreSlot::Lent(Some(caller));
+            drop(slot);
+            // SAFETY: the caller outlives the import that lent it, and the
+            // import restores the slot before returning.
+            f(unsafe { caller.as_mut() }.as_context_mut())
+        }
+        StoreSlot::Lent(None) => Err(Exception::throw_message(
+            ctx,
+            "the WebAssembly instance is running",
+        )),
+    }
+}
+
+fn get_memory(
+    ctx: &Ctx<'_>,
+    instance: Instance,
+    store: &StoreContextMut<'_, HostState>,
+    name: &str,
+) -> rquickjs::Result<Memory> {
+    instance
+        .get_export(store, name)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Exception::throw_type(ctx, &format!("{name} is not a memory")))
+}
+
+fn call_export<'js>(
+    ctx: &Ctx<'js>,
+    instance: &Class<'js, WasmInstance<'js>>,
+    name: &str,
+    args: Vec<Value<'js>>,
+) -> rquickjs::Result<Value<'js>> {
+    let imports = instance.borrow().imports.clone();
+    let (instance, store) = handles(instance);
+
+    let results = with_store(ctx, &store, |mut store| {
+        let func = match instance
+            .get_export(&store, name)
+            .and_then(Extern::into_func)
+        {
+            Some(func) => func,
+            None => {
+                return Err(Exception::throw_type(
+                    ctx,
+                    &format!("{name} is not a function"),
+                ))
+            }
+        };
+
+        let ty = func.ty(&store);
+        let mut params = Vec::with_capacity(ty.params().len());
+        for (i, param) in ty.params().iter().enumerate() {
+            let value = args
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| Value::new_undefined(ctx.clone()));
+            params.push(to_val(ctx, value, *param)?);
+        }
+        let mut results: Vec<Val> = ty
+            .results()
+            .iter()
+            .map(|ty| Val::default_for_ty(*ty))
+            .collect();
+
+        let previous = store
+            .data_mut()
+            .imports
+            .replace(Persistent::save(ctx, imports));
+        let ret = func.call(&mut store, &params, &mut results);
+        store.data_mut().imports = previous;
+        ret.map_err(|e| Exception::throw_message(ctx, &e.to_string()))?;
+        Ok(results)
+    })?;
+    // the call may have grown a memory
+    sync_buffers(ctx, &store)?;
+
+    let mut results = results;
+    match results.len() {
+        0 => Ok(Value::new_undefined(ctx.clone())),
+        1 => from_val(ctx, results.remove(0)),
+        _ => {
+            let array = Array::new(ctx.clone())?;
+            for (i, result) in results.into_iter().enumerate() {
+                array.set(i, from_val(ctx, result)?)?;
+            }
+            Ok(array.into_value())
+        }
+    }
+}
+
+fn call_import(
+    mut caller: Caller<'_, HostState>,
+    index: usize,
+    ty: &wasmi::FuncType,
+    params: &[Val],
+    results: &mut [Val],
+) -> Result<(), wasmi::Error> {
+    let host = caller.data();
+    // SAFETY: the store is only used by the worker thread, while it runs JS
+    // and holds the runtime lock.
+    let ctx = unsafe { Ctx::from_raw(host.ctx) };
+    let store = host
+        .store
+        .upgrade()
+        .ok_or_else(|| wasmi::Error::new("the WebAssembly instance was freed"))?;
+    let imports = host
+        .imports
+        .clone()
+        .ok_or_else(|| wasmi::Error::new("imported function called outside of a JS call"))?;
+
+    // lend the store to the functions the import calls
+    let lent = StoreSlot::Lent(Some(NonNull::from(&mut caller).cast()));
+    let previous = mem::replace(&mut *store.slot.borrow_mut(), lent);
+
+    let invoke = || -> rquickjs::Result<()> {
+        // wasm code may have grown a memory before calling out
+        sync_buffers(&ctx, &store)?;
+        let imports = imports.restore(&ctx)?;
+        let callback: Function = imports.get(index)?;
+        let args = params
+            .iter()
+            .map(|param| from_val(&ctx, param.clone()))
+            .collect::<rquickjs::Result<Vec<_>>>()?;
+        let ret: Value = callback.call((Rest(args),))?;
+
+        if let Some(result_ty) = ty.results().first() {
+            results[0] = to_val(&ctx, ret, *result_ty)?;
+        }
+        Ok(())
+    };
+    let ret = invoke();
+    *store.slot.borrow_mut() = previous;
+
+    ret.map_err(|e| match e {
+        rquickjs::Error::Exception => {
+            let exception = ctx.catch();
+            wasmi::Error::new(format!("{exception:?}"))
+        }
+        e => wasmi::Error::new(e.to_string()),
+    })
+}
+
+/// Returns an `ArrayBuffer` over the live data of a memory. The same buffer
+/// is returned until the memory grows, which detaches it.
+fn memory_buffer<'js>(
+    ctx: &Ctx<'js>,
+    instance: Instance,
+    store: &Rc<InstanceStore>,
+    name: &str,
+) -> rquickjs::Result<Value<'js>> {
+    sync_buffers(ctx, store)?;
+    let existing = store
+        .buffers
+        .borrow()
+        .iter()
+        .find(|buffer| buffer.name == name)
+        .map(|buffer| buffer.object);
+    if let Some(object) = existing {
+        // SAFETY: buffers remove themselves when collected
+        return Ok(unsafe { Value::from_raw(ctx.clone(), qjs::JS_DupValue(object)) });
+    }
+
+    let (memory, data) = with_store(ctx, store, |store| {
+        let memory = get_memory(ctx, instance, &store, name)?;
+        Ok((memory, (memory.data_ptr(&store), memory.data_size(&store))))
+    })?;
+    let id = store.next_buffer.get();
+    store.next_buffer.set(id + 1);
+    let owner = NonNull::from(Box::leak(Box::new(BufferOwner {
+        store: store.clone(),
+        id,
+        detaching: Cell::new(false),
+    })));
+    // SAFETY: the data stays valid until the memory grows or moves, which
+    // detaches the buffer, and the owner keeps the store alive.
+    let object = unsafe {
+        qjs::JS_NewArrayBuffer(
+            ctx.as_raw().as_ptr(),
+            data.0,
+            data.1 as _,
+            Some(free_buffer),
+            owner.as_ptr().cast(),
+            0,
+        )
+    };
+    if unsafe { qjs::JS_VALUE_GET_TAG(object) } == qjs::JS_TAG_EXCEPTION {
+        drop(unsafe { Box::from_raw(owner.as_ptr()) });
+        return Err(rquickjs::Error::Exception);
+    }
+    store.buffers.borrow_mut().push(LiveBuffer {
+        id,
+        name: name.to_string(),
+        memory,
+        data,
+        object,
+        owner,
+    });
+    Ok(unsafe { Value::from_raw(ctx.clone(), object) })
+}
+
+/// Detaches the buffers of memories that grew or moved, as growing a
+/// memory does in browsers.
+fn sync_buffers(ctx: &Ctx<'_>, store: &InstanceStore) -> rquickjs::Result<()> {
+    if store.buffers.borrow().is_empty() {
+        return Ok(());
+    }
+    let stale = with_store(ctx, store, |store_ctx| {
+        let mut buffers = store.buffers.borrow_mut();
+        let (stale, live) = mem::take(&mut *buffers).into_iter().partition(|buffer| {
+            let data = (
+                buffer.memory.data_ptr(&store_ctx),
+                buffer.memory.data_size(&store_ctx),
+            );
+            data != buffer.data
+        });
+        *buffers = live;
+        Ok(stale)
+    })?;
+    for buffer in stale {
+        // SAFETY: the buffer is alive until `free_buffer` runs, and the owner
+        // is only freed by the finalizer once detached.
+        unsafe {
+            buffer.owner.as_ref().detaching.set(true);
+            qjs::JS_DetachArrayBuffer(ctx.as_raw().as_ptr(), buffer.object);
+        }
+    }
+    Ok(())
+}
+
+/// Called when a buffer is detached, and again when it is collected.
+unsafe extern "C" fn free_buffer(_rt: *mut qjs::JSRuntime, opaque: *mut c_void, _ptr: *mut c_void) {
+    let owner = opaque.cast::<BufferOwner>();
+    if (*owner).detaching.replace(false) {
+        return;
+    }
+    let owner = Box::from_raw(owner);
+    owner
+        .store
+        .buffers
+        .borrow_mut()
+        .retain(|buffer| buffer.id != owner.id);
+}
+
+fn kind(ty: &ExternType) -> &'static str {
+    match ty {
+        ExternType::Func(_) => "function",
+        ExternType::Global(_) => "global",
+        ExternType::Memory(_) => "memory",
+        ExternType::Table(_) => "table",
+    }
+}
+
+/// Reads the bytes of a `BufferSource` (a typed array or an `ArrayBuffer`).
+fn to_bytes(value: &Value<'_>) -> rquickjs::Result<Vec<u8>> {
+    if let Ok(array) = TypedArray::<u8>::from_value(value.clone()) {
+        if let Some(bytes) = array.as_bytes() {
+            return Ok(bytes.to_vec());
+        }
+    }
+    if let Some(buffer) = ArrayBuffer::from_value(value.clone()) {
+        if let Some(bytes) = buffer.as_bytes() {
+            return Ok(bytes.to_vec());
+        }
+    }
+    Err(Exception::throw_type(
+        value.ctx(),
+        "argument must be a BufferSource",
+    ))
+}
+
+fn to_val<'js>(ctx: &Ctx<'js>, value: Value<'js>, ty: ValType) -> rquickjs::Result<Val> {
+    let number = match value.as_big_int() {
+        Some(big_int) => big_int.clone().to_i64()? as f64,
+        None => value.as_number().unwrap_or(f64::NAN),
+    };
+
+    Ok(match ty {
+        ValType::I32 => Val::I32(number as i64 as i32),
+        ValType::I64 => match value.as_big_int() {
+            Some(big_int) => Val::I64(big_int.clone().to_i64()?),
+            None => Val::I64(number as i64),
+        },
+        ValType::F32 => Val::F32(F32::from_float(number as f32)),
+        ValType::F64 => Val::F64(F64::from_float(number)),
+        _ => {
+            return Err(Exception::throw_type(
+                ctx,
+                "unsupported WebAssembly value type",
+            ))
+        }
+    })
+}
+
+fn from_val<'js>(ctx: &Ctx<'js>, val: Val) -> rquickjs::Result<Value<'js>> {
+    Ok(match val {
+        Val::I32(v) => Value::new_int(ctx.clone(), v),
+        Val::I64(v) => BigInt::from_i64(ctx.clone(), v)?.into_value(),
+        Val::F32(v) => Value::new_float(ctx.clone(), v.to_float() as f64),
+        Val::F64(v) => Value::new_float(ctx.clone(), v.to_float()),
+        _ => {
+            return Err(Exception::throw_type(
+                ctx,
+                "unsupported WebAssembly value type",
+            ))
+        }
+    })
+}
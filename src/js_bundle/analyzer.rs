@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use askama::Template;
+use serde::Serialize;
+use swc_common::source_map::LineCol;
+use swc_common::source_map::SmallPos;
+use swc_common::sync::Lrc;
+use swc_common::BytePos;
+use swc_common::FileName;
+use swc_common::SourceMap;
+use swc_common::Span;
+use swc_common::Spanned;
+use swc_ecma_ast::Decl;
+use swc_ecma_ast::DefaultDecl;
+use swc_ecma_ast::ExportSpecifier;
+use swc_ecma_ast::Expr;
+use swc_ecma_ast::Ident;
+use swc_ecma_ast::Module;
+use swc_ecma_ast::ModuleDecl;
+use swc_ecma_ast::ModuleExportName;
+use swc_ecma_ast::ModuleItem;
+use swc_ecma_ast::Stmt;
+use swc_ecma_utils::find_pat_ids;
+use swc_ecma_visit::Visit;
+use swc_ecma_visit::VisitWith;
+
+use super::modules::ImportType;
+
+/// What ended up in a bundle and why, see `Options::analyze`.
+#[derive(Debug, Serialize)]
+pub struct Analysis {
+    pub entry: String,
+    pub output_size: usize,
+    pub modules: Vec<ModuleAnalysis>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleAnalysis {
+    pub specifier: String,
+    pub import_type: String,
+    /// Size of the module's source, before transpiling.
+    pub original_size: usize,
+    /// Bytes of the bundle generated from this module.
+    pub output_size: usize,
+    pub exports: Vec<ExportAnalysis>,
+    /// Modules importing each other from the entry down to this one.
+    pub import_chain: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportAnalysis {
+    pub name: String,
+    /// Whether the export survived tree-shaking.
+    pub kept: bool,
+}
+
+#[derive(Template)]
+#[template(path = "../templates/analyze.html.j2")]
+struct AnalysisHtml<'a> {
+    analysis: &'a Analysis,
+}
+
+impl Analysis {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_html(&self) -> Result<String> {
+        Ok(AnalysisHtml { analysis: self }.render()?)
+    }
+}
+
+/// A module seen by the loader.
+struct LoadedModule {
+    original_size: usize,
+    /// Exported names with the span of the declaration they refer to.
+    exports: Vec<(String, Span)>,
+}
+
+/// Records modules and import edges while bundling.
+#[derive(Default)]
+pub(super) struct Analyzer {
+    modules: Mutex<BTreeMap<String, LoadedModule>>,
+    imports: Mutex<Vec<(String, String)>>,
+}
+
+impl Analyzer {
+    pub fn record_module(&self, specifier: &str, source_size: usize, module: &Module) {
+        let original_size = ImportType::untag(specifier)
+            .ok()
+            .and_then(|(path, _)| Path::new(path).metadata().ok())
+            .map(|metadata| metadata.len() as usize)
+            .unwrap_or(source_size);
+
+        self.modules.lock().unwrap().insert(
+            specifier.to_string(),
+            LoadedModule {
+                original_size,
+                exports: collect_exports(module),
+            },
+        );
+    }
+
+    pub fn record_import(&self, base: &FileName, resolved: &FileName) {
+        self.imports
+            .lock()
+            .unwrap()
+            .push((base.to_string(), resolved.to_string()));
+    }
+
+    /// Builds the report from the bundled module (before minification) and
+    /// the emitted code with its source map entries.
+    pub fn finish(
+        self,
+        cm: &Lrc<SourceMap>,
+        entry: &str,
+        bundled: &Module,
+        code: &str,
+        srcmap: &[(BytePos, LineCol)],
+    ) -> Analysis {
+        let modules = self.modules.into_inner().unwrap();
+        let imports = self.imports.into_inner().unwrap();
+
+        let mut spans = OutputSpans::default();
+        bundled.visit_with(&mut spans);
+        let output_sizes = attribute_output(cm, code, srcmap);
+        let chains = import_chains(entry, &imports);
+
+        let modules = modules
+            .into_iter()
+            .map(|(specifier, module)| {
+                let (path, import_type) = ImportType::untag(&specifier)
+                    .map(|(path, import_type)| (path.to_string(), import_type))
+                    .unwrap_or((specifier.clone(), ImportType::JavaScript));
+                let exports = module
+                    .exports
+                    .into_iter()
+                    .map(|(name, span)| ExportAnalysis {
+                        name,
+                        kept: spans.0.contains(&span),
+                    })
+                    .collect();
+                let import_chain = chains
+                    .get(&specifier)
+                    .map(|chain| chain.iter().map(|s| untagged(s)).collect())
+                    .unwrap_or_default();
+
+                ModuleAnalysis {
+                    output_size: output_sizes.get(&specifier).copied().unwrap_or_default(),
+                    specifier: path,
+                    import_type: import_type.as_str().to_string(),
+                    original_size: module.original_size,
+                    exports,
+                    import_chain,
+                }
+            })
+            .collect();
+
+        Analysis {
+            entry: entry.to_string(),
+            output_size: code.len(),
+            modules,
+        }
+    }
+}
+
+fn untagged(specifier: &str) -> String {
+    ImportType::untag(specifier)
+        .map(|(path, _)| path.to_string())
+        .unwrap_or_else(|_| specifier.to_string())
+}
+
+/// Lists a module's own exports. Re-exports from other modules are reported
+/// by the module that declares them.
+fn collect_exports(module: &Module) -> Vec<(String, Span)> {
+    let mut declarations = HashMap::new();
+    let declare = |decl: &Decl, declarations: &mut HashMap<String, Span>| match decl {
+        Decl::Fn(f) => {
+            declarations.insert(f.ident.sym.to_string(), f.ident.span);
+        }
+        Decl::Class(c) => {
+            declarations.insert(c.ident.sym.to_string(), c.ident.span);
+        }
+        Decl::Var(v) => {
+            for ident in find_pat_ids::<_, Ident>(&v.decls) {
+                declarations.insert(ident.sym.to_string(), ident.span);
+            }
+        }
+        _ => {}
+    };
+
+    for item in &module.body {
+        match item {
+            ModuleItem::Stmt(Stmt::Decl(decl)) => declare(decl, &mut declarations),
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                declare(&export.decl, &mut declarations)
+            }
+            _ => {}
+        }
+    }
+
+    let mut exports = vec![];
+    for item in &module.body {
+        let ModuleItem::ModuleDecl(decl) = item else {
+            continue;
+        };
+        match decl {
+            ModuleDecl::ExportDecl(export) => {
+                let names: Vec<String> = match &export.decl {
+                    Decl::Fn(f) => vec![f.ident.sym.to_string()],
+                    Decl::Class(c) => vec![c.ident.sym.to_string()],
+                    Decl::Var(v) => find_pat_ids::<_, Ident>(&v.decls)
+                        .into_iter()
+                        .map(|ident| ident.sym.to_string())
+                        .collect(),
+                    _ => vec![],
+                };
+                exports.extend(names.into_iter().map(|name| {
+                    let span = declarations[&name];
+                    (name, span)
+                }));
+            }
+            ModuleDecl::ExportNamed(named) if named.src.is_none() => {
+                for specifier in &named.specifiers {
+                    let ExportSpecifier::Named(specifier) = specifier else {
+                        continue;
+                    };
+                    let ModuleExportName::Ident(orig) = &specifier.orig else {
+                        continue;
+                    };
+                    let name = match &specifier.exported {
+                        Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                        Some(ModuleExportName::Str(s)) => s.value.to_string(),
+                        None => orig.sym.to_string(),
+                    };
+                    if let Some(span) = declarations.get(&*orig.sym) {
+                        exports.push((name, *span));
+                    }
+                }
+            }
+            ModuleDecl::ExportDefaultDecl(export) => {
+                let span = match &export.decl {
+                    DefaultDecl::Fn(f) => f.ident.as_ref().map_or(f.function.span, |i| i.span),
+                    DefaultDecl::Class(c) => c.ident.as_ref().map_or(c.class.span, |i| i.span),
+                    DefaultDecl::TsInterfaceDecl(_) => continue,
+                };
+                exports.push(("default".to_string(), span));
+            }
+            ModuleDecl::ExportDefaultExpr(export) => {
+                let span = match &*export.expr {
+                    Expr::Ident(ident) => declarations.get(&*ident.sym).copied(),
+                    _ => None,
+                };
+                exports.push(("default".to_string(), span.unwrap_or(export.expr.span())));
+            }
+            _ => {}
+        }
+    }
+
+    exports
+}
+
+/// Collects every span present in the bundled module.
+#[derive(Default)]
+struct OutputSpans(HashSet<Span>);
+
+impl Visit for OutputSpans {
+    fn visit_span(&mut self, span: &Span) {
+        self.0.insert(*span);
+    }
+}
+
+/// Splits the emitted code between the source files its source map entries
+/// point to. Code generated by the bundler itself is not attributed.
+fn attribute_output(
+    cm: &Lrc<SourceMap>,
+    code: &str,
+    srcmap: &[(BytePos, LineCol)],
+) -> HashMap<String, usize> {
+    let lines: Vec<usize> = std::iter::once(0)
+        .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    // Columns are counted in chars.
+    let offset = |loc: &LineCol| -> usize {
+        let Some(&start) = lines.get(loc.line as usize) else {
+            return code.len();
+        };
+        code[start..]
+            .char_indices()
+            .nth(loc.col as usize)
+            .map_or(code.len(), |(i, _)| start + i)
+    };
+
+    let mut entries: Vec<(usize, BytePos)> = srcmap
+        .iter()
+        .map(|(pos, loc)| (offset(loc), *pos))
+        .collect();
+    entries.sort_by_key(|(offset, _)| *offset);
+
+    let mut sizes = HashMap::new();
+    for (i, (start, pos)) in entries.iter().enumerate() {
+        let end = entries.get(i + 1).map_or(code.len(), |(end, _)| *end);
+        if pos.is_dummy() || pos.is_reserved_for_comments() || end <= *start {
+            continue;
+        }
+        if let Ok(file) = cm.try_lookup_source_file(*pos) {
+            // Positions at the very end of a file belong to it as well.
+            if pos.to_usize() <= file.end_pos.to_usize() {
+                *sizes.entry(file.name.to_string()).or_default() += end - start;
+            }
+        }
+    }
+    sizes
+}
+
+/// Finds the shortest chain of imports from the entry to every module.
+fn import_chains(entry: &str, imports: &[(String, String)]) -> HashMap<String, Vec<String>> {
+    let mut chains = HashMap::from([(entry.to_string(), vec![entry.to_string()])]);
+    let mut queue = VecDeque::from([entry.to_string()]);
+
+    while let Some(base) = queue.pop_front() {
+        let chain = chains[&base].clone();
+        for (_, resolved) in imports.iter().filter(|(from, _)| *from == base) {
+            if !chains.contains_key(resolved) {
+                let mut chain = chain.clone();
+                chain.push(resolved.clone());
+                chains.insert(resolved.clone(), chain);
+                queue.push_back(resolved.clone());
+            }
+        }
+    }
+    chains
+}
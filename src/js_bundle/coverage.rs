@@ -0,0 +1,362 @@
+use std::mem;
+
+use serde::Deserialize;
+use serde::Serialize;
+use swc_common::sync::Lrc;
+use swc_common::util::take::Take;
+use swc_common::FileName;
+use swc_common::SourceMap;
+use swc_common::Span;
+use swc_common::Spanned;
+use swc_common::DUMMY_SP;
+use swc_ecma_ast::ArrowExpr;
+use swc_ecma_ast::BlockStmt;
+use swc_ecma_ast::BlockStmtOrExpr;
+use swc_ecma_ast::ComputedPropName;
+use swc_ecma_ast::CondExpr;
+use swc_ecma_ast::Decl;
+use swc_ecma_ast::EsVersion;
+use swc_ecma_ast::Expr;
+use swc_ecma_ast::ExprStmt;
+use swc_ecma_ast::Ident;
+use swc_ecma_ast::IdentName;
+use swc_ecma_ast::IfStmt;
+use swc_ecma_ast::Lit;
+use swc_ecma_ast::MemberExpr;
+use swc_ecma_ast::MemberProp;
+use swc_ecma_ast::Module;
+use swc_ecma_ast::ModuleDecl;
+use swc_ecma_ast::ModuleItem;
+use swc_ecma_ast::Number;
+use swc_ecma_ast::ReturnStmt;
+use swc_ecma_ast::SeqExpr;
+use swc_ecma_ast::Stmt;
+use swc_ecma_ast::SwitchStmt;
+use swc_ecma_ast::UpdateExpr;
+use swc_ecma_ast::UpdateOp;
+use swc_ecma_parser::parse_file_as_module;
+use swc_ecma_parser::EsSyntax;
+use swc_ecma_parser::Syntax;
+use swc_ecma_utils::DropSpan;
+use swc_ecma_visit::VisitMut;
+use swc_ecma_visit::VisitMutWith;
+
+/// Global the counters of instrumented modules are kept in, by file.
+pub const COVERAGE_GLOBAL: &str = "$$cov";
+
+/// What the counters of an instrumented module stand for, see
+/// `Options::coverage`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageMap {
+    /// Source line of each statement counter.
+    pub statements: Vec<u32>,
+    /// Source location of each branch counter.
+    pub branches: Vec<BranchLocation>,
+}
+
+/// A branch of an `if`, `? :` or `switch`. Branches of the same decision
+/// share a block number.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchLocation {
+    pub line: u32,
+    pub block: u32,
+    pub branch: u32,
+}
+
+/// Counters collected from a worker for one module, indexed like its
+/// [`CoverageMap`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageCounters {
+    pub s: Vec<u64>,
+    pub b: Vec<u64>,
+}
+
+/// Inserts counters into a module, before each statement and at the start of
+/// each branch, and returns what they stand for. Lines are taken from the
+/// spans, so they point into the original TypeScript.
+pub fn instrument(cm: &Lrc<SourceMap>, file: &str, module: &mut Module) -> CoverageMap {
+    let hash = blake3::hash(file.as_bytes()).to_hex();
+    let mut instrumenter = Instrumenter {
+        cm: cm.clone(),
+        counters: Ident::new_no_ctxt(format!("__cov_{}", &hash[..8]).into(), DUMMY_SP),
+        map: CoverageMap::default(),
+        blocks: 0,
+    };
+    module.visit_mut_with(&mut instrumenter);
+
+    let map = instrumenter.map;
+    let header = format!(
+        "var {}=(function(all){{return all[{file}]||(all[{file}]={{s:new Array({}).fill(0),b:new Array({}).fill(0)}});}})(globalThis.{COVERAGE_GLOBAL}||(globalThis.{COVERAGE_GLOBAL}={{}}));",
+        instrumenter.counters.sym,
+        map.statements.len(),
+        map.branches.len(),
+        file = serde_json::to_string(file).unwrap(),
+    );
+    let fm = cm.new_source_file(
+        Lrc::new(FileName::Custom(format!("coverage:{file}"))),
+        header,
+    );
+    let mut header = parse_file_as_module(
+        &fm,
+        Syntax::Es(EsSyntax::default()),
+        EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .expect("coverage header should parse");
+    header.visit_mut_with(&mut DropSpan);
+    module.body.splice(0..0, header.body);
+    map
+}
+
+struct Instrumenter {
+    cm: Lrc<SourceMap>,
+    /// The module's entry of `COVERAGE_GLOBAL`.
+    counters: Ident,
+    map: CoverageMap,
+    blocks: u32,
+}
+
+impl Instrumenter {
+    fn line(&self, span: Span) -> u32 {
+        self.cm.lookup_char_pos(span.lo).line as u32
+    }
+
+    /// `__cov_x.<kind>[index]++`
+    fn counter(&self, kind: &str, index: usize) -> Box<Expr> {
+        let member = MemberExpr {
+            span: DUMMY_SP,
+            obj: Box::new(Expr::Member(MemberExpr {
+                span: DUMMY_SP,
+                obj: Box::new(Expr::Ident(self.counters.clone())),
+                prop: MemberProp::Ident(IdentName::new(kind.into(), DUMMY_SP)),
+            })),
+            prop: MemberProp::Computed(ComputedPropName {
+                span: DUMMY_SP,
+                expr: Box::new(Expr::Lit(Lit::Num(Number {
+                    span: DUMMY_SP,
+                    value: index as f64,
+                    raw: None,
+                }))),
+            }),
+        };
+        Box::new(Expr::Update(UpdateExpr {
+            span: DUMMY_SP,
+            op: UpdateOp::PlusPlus,
+            prefix: false,
+            arg: Box::new(Expr::Member(member)),
+        }))
+    }
+
+    /// Counts a statement, unless there is nothing to run in it.
+    fn statement(&mut self, stmt: &Stmt) -> Option<Stmt> {
+        let countable = match stmt {
+            Stmt::Empty(_) | Stmt::Block(_) | Stmt::Decl(Decl::Fn(_)) => false,
+            // directives, e.g. "use strict"
+            Stmt::Expr(ExprStmt { expr, .. }) => !matches!(**expr, Expr::Lit(Lit::Str(_))),
+            _ => true,
+        };
+        self.count_statement(countable, stmt.span())
+    }
+
+    fn count_statement(&mut self, countable: bool, span: Span) -> Option<Stmt> {
+        if !countable || span.is_dummy() {
+            return None;
+        }
+        self.map.statements.push(self.line(span));
+        Some(expr_stmt(self.counter("s", self.map.statements.len() - 1)))
+    }
+
+    /// Starts a decision with `branches` branches at `span`, and returns the
+    /// index of the first branch counter, if the decision is in the sources.
+    fn decision(&mut self, span: Span, branches: u32) -> Option<usize> {
+        if span.is_dummy() {
+            return None;
+        }
+        let line = self.line(span);
+        let first = self.map.branches.len();
+        for branch in 0..branches {
+            self.map.branches.push(BranchLocation {
+                line,
+                block: self.blocks,
+                branch,
+            });
+        }
+        self.blocks += 1;
+        Some(first)
+    }
+}
+
+impl VisitMut for Instrumenter {
+    fn visit_mut_module_items(&mut self, items: &mut Vec<ModuleItem>) {
+        items.visit_mut_children_with(self);
+        for item in mem::take(items) {
+            let counter = match &item {
+                ModuleItem::Stmt(stmt) => self.statement(stmt),
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                    let countable = !matches!(export.decl, Decl::Fn(_));
+                    self.count_statement(countable, export.span)
+                }
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+                    self.count_statement(true, export.span)
+                }
+                ModuleItem::ModuleDecl(_) => None,
+            };
+            items.extend(counter.map(ModuleItem::Stmt));
+            items.push(item);
+        }
+    }
+
+    fn visit_mut_stmts(&mut self, stmts: &mut Vec<Stmt>) {
+        stmts.visit_mut_children_with(self);
+        for stmt in mem::take(stmts) {
+            stmts.extend(self.statement(&stmt));
+            stmts.push(stmt);
+        }
+    }
+
+    fn visit_mut_stmt(&mut self, stmt: &mut Stmt) {
+        // Bodies get blocks, so that their statements can be counted.
+        match stmt {
+            Stmt::If(n) => {
+                into_block(&mut n.cons);
+                if let Some(alt) = &mut n.alt {
+                    into_block(alt);
+                }
+            }
+            Stmt::For(n) => into_block(&mut n.body),
+            Stmt::ForIn(n) => into_block(&mut n.body),
+            Stmt::ForOf(n) => into_block(&mut n.body),
+            Stmt::While(n) => into_block(&mut n.body),
+            Stmt::DoWhile(n) => into_block(&mut n.body),
+            _ => {}
+        }
+        stmt.visit_mut_children_with(self);
+    }
+
+    fn visit_mut_if_stmt(&mut self, n: &mut IfStmt) {
+        n.visit_mut_children_with(self);
+        let Some(first) = self.decision(n.span, 2) else {
+            return;
+        };
+        let alt = n
+            .alt
+            .get_or_insert_with(|| Box::new(Stmt::Block(BlockStmt::default())));
+        for (branch, index) in [(&mut n.cons, first), (alt, first + 1)] {
+            if let Stmt::Block(block) = &mut **branch {
+                block.stmts.insert(0, expr_stmt(self.counter("b", index)));
+            }
+        }
+    }
+
+    fn visit_mut_cond_expr(&mut self, n: &mut CondExpr) {
+        n.visit_mut_children_with(self);
+        let Some(first) = self.decision(n.span, 2) else {
+            return;
+        };
+        for (branch, index) in [(&mut n.cons, first), (&mut n.alt, first + 1)] {
+            let expr = branch.take();
+            **branch = Expr::Seq(SeqExpr {
+                span: DUMMY_SP,
+                exprs: vec![self.counter("b", index), expr],
+            });
+        }
+    }
+
+    fn visit_mut_switch_stmt(&mut self, n: &mut SwitchStmt) {
+        n.visit_mut_children_with(self);
+        let Some(first) = self.decision(n.span, n.cases.len() as u32) else {
+            return;
+        };
+        for (i, case) in n.cases.iter_mut().enumerate() {
+            case.cons.insert(0, expr_stmt(self.counter("b", first + i)));
+        }
+    }
+
+    fn visit_mut_arrow_expr(&mut self, n: &mut ArrowExpr) {
+        // `() => x` becomes `() => { return x }`, so that `x` can be counted
+        if let BlockStmtOrExpr::Expr(expr) = &mut *n.body {
+            let span = expr.span();
+            let expr = expr.take();
+            *n.body = BlockStmtOrExpr::BlockStmt(BlockStmt {
+                span,
+                stmts: vec![Stmt::Return(ReturnStmt {
+                    span,
+                    arg: Some(expr),
+                })],
+                ..Default::default()
+            });
+        }
+        n.visit_mut_children_with(self);
+    }
+}
+
+fn expr_stmt(expr: Box<Expr>) -> Stmt {
+    Stmt::Expr(ExprStmt {
+        span: DUMMY_SP,
+        expr,
+    })
+}
+
+fn into_block(stmt: &mut Box<Stmt>) {
+    if !matches!(**stmt, Stmt::Block(_)) {
+        let span = stmt.span();
+        let inner = stmt.take();
+        **stmt = Stmt::Block(BlockStmt {
+            span,
+            stmts: vec![*inner],
+            ..Default::default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js_bundle::media_types::MediaType;
+    use crate::js_bundle::transpilers::parse_module;
+    use swc_common::Globals;
+    use swc_common::GLOBALS;
+
+    #[test]
+    fn instrument_should_count_statements_and_branches() {
+        let cm = Lrc::new(SourceMap::default());
+        let source = r#"
+function sign(n: number): string {
+  if (n < 0) return "-";
+  return n > 0 ? "+" : "";
+}
+const f = (x: number) => x * 2;
+switch (sign(-1)) {
+  case "-":
+    f(1);
+    break;
+  default:
+}
+"#;
+        let fm = cm.new_source_file(Lrc::new(FileName::Real("/sign.ts".into())), source.into());
+        GLOBALS.set(&Globals::default(), || {
+            let mut module = parse_module(&cm, &fm, MediaType::TypeScript, None).unwrap();
+            let map = instrument(&cm, "/sign.ts", &mut module);
+
+            // nested statements are counted before the ones around them
+            assert_eq!(map.statements, vec![3, 3, 4, 6, 9, 10, 6, 7]);
+            let decisions: Vec<_> = map
+                .branches
+                .iter()
+                .map(|b| (b.line, b.block, b.branch))
+                .collect();
+            assert_eq!(
+                decisions,
+                vec![
+                    (3, 0, 0),
+                    (3, 0, 1),
+                    (4, 1, 0),
+                    (4, 1, 1),
+                    (7, 2, 0),
+                    (7, 2, 1)
+                ]
+            );
+        });
+    }
+}
@@ -11,7 +11,7 @@
 
 import fs from 'fs';
 import { performance } from 'perf_hooks';
-import { bg_green, bg_red, red, green, bold } from 'colors';
+import { bg_green, bg_red, red, green, bold, bright_black } from 'colors';
 
 // Output labels.
 const OK = bg_green(bold(' OK '));
@@ -64,6 +64,8 @@ export class TestRunner {
     this.testFiles = [];
     this.filter = undefined;
     this.failFast = false;
+    // Set by `dino test`, which reports the results itself.
+    this.silent = false;
     this.counters = {
       ok: 0,
       failed: 0,
@@ -128,11 +130,15 @@ export class TestRunner {
   }
 
   /**
-   * Runs all the registered tests as a test suite.
+   * Runs all the registered tests as a test suite. Unless silent, it then
+   * prints a summary and exits, with a non-zero code if a test failed.
+   *
+   * @returns {Promise<Object>} The counters, the suite's duration and the result of each test.
    */
   async run() {
     // Start test suite clock.
     const startTime = performance.now();
+    const results = [];
 
     // Run test suite.
     for await (const [description, testFn] of this.tests) {
@@ -141,19 +147,36 @@ export class TestRunner {
         continue;
       }
 
+      const { location } = testFn;
+
       // Check if the test should be ignored.
       if (testFn.ignore) {
         this.counters.ignored++;
+        results.push({ description, status: 'ignored', duration: 0, location });
         continue;
       }
 
+      const testStart = performance.now();
       try {
         await timeout(testFn(), testFn.timeout);
+        const duration = performance.now() - testStart;
         this.counters.ok++;
-        console.log(`${OK} ${green(description)}`);
+        results.push({ description, status: 'ok', duration, location });
+        if (!this.silent) {
+          console.log(`${OK} ${green(description)} ${bright_black(`(${Math.trunc(duration)} ms)`)}`);
+        }
       } catch (err) {
+        const duration = performance.now() - testStart;
+        const error =
+          err instanceof Error
+            ? { name: err.name, message: err.message, stack: err.stack?.trimEnd() }
+            : { name: typeof err, message: String(err) };
         this.counters.failed++;
-        console.log(`${FAIL} ${red(description)}\n ${red(err.stack)}`);
+        results.push({ description, status: 'failed', duration, location, error });
+        if (!this.silent) {
+          const details = error.stack ? `${err}\n${error.stack}` : `${err}`;
+          console.log(`${FAIL} ${red(description)}\n ${red(details)}`);
+        }
 
         // Stop running test suite.
         if (this.failFast) {
@@ -165,20 +188,73 @@ export class TestRunner {
       }
     }
 
-    const { ok, failed, ignored } = this.counters;
+    const duration = performance.now() - startTime;
+    if (!this.silent) {
+      const { ok, failed, ignored } = this.counters;
+      const result = `${ok} ok; ${failed} failed; ${ignored} ignored`;
+      console.log(`\nTest result: ${result} (${Math.trunc(duration)} ms)`);
 
-    // Create output strings.
-    const elapsedTime = Math.trunc(performance.now() - startTime);
-    const result = `${ok} ok; ${failed} failed; ${ignored} ignored`;
+      // Exit with non-zero code if we have test failure.
+      process.exit(failed > 0 ? 1 : 0);
+    }
+    return { ...this.counters, duration, results };
+  }
+}
+
+export const mainRunner = new TestRunner();
 
-    console.log(`\nTest result: ${result} (${elapsedTime} ms)`);
+// Expose the runner so that `dino test` can run the registered tests.
+globalThis.$$mainRunner = mainRunner;
 
-    // Exit with non-zero code if we have test failure.
-    process.exit(failed > 0 ? 1 : 0);
+/**
+ * Sends a request through the project's config.yml routes to its handler,
+ * like `dino run` would, without opening a socket.
+ *
+ * The handlers run in their own copy of main.ts: a test importing main.ts
+ * gets another one, so module state, like a variable a handler updates,
+ * isn't shared between the two.
+ *
+ * @param {string} url - A path like `/api/users/1`, or a full URL.
+ * @param {Object} [init] - Options of the request.
+ * @param {string} [init.method] - The HTTP method, `GET` by default.
+ * @param {Object} [init.headers] - The headers, with case-insensitive names.
+ * @param {*} [init.body] - The body, serialized to JSON unless a string.
+ * @returns {Promise<Object>} The handler's response: `status`, `headers` and `body`.
+ */
+export async function request(url, init = {}) {
+  if (typeof globalThis.$$route !== 'function') {
+    throw new Error('request() needs the routes of the project, run the tests with `dino test`.');
+  }
+  const method = (init.method ?? 'GET').toUpperCase();
+  const headers = {};
+  for (const [name, value] of Object.entries(init.headers ?? {})) {
+    headers[name.toLowerCase()] = String(value);
+  }
+  let body = init.body;
+  if (body !== undefined && typeof body !== 'string') {
+    body = JSON.stringify(body);
+    headers['content-type'] ??= 'application/json';
+  }
+
+  const route = $$route(method, url);
+  if (route.status) {
+    return { status: route.status, headers: {}, body: undefined };
+  }
+  const handler = globalThis.handlers?.[route.handler];
+  if (typeof handler !== 'function') {
+    throw new Error(`The handler "${route.handler}" of ${method} ${url} isn't exported by main.ts.`);
   }
+  return await handler({ method, url: route.url, header: headers, params: route.params, body });
 }
 
-export const mainRunner = new TestRunner();
+// Location (`file:line:column`) of the code calling `test()`.
+function callerLocation() {
+  const frames = new Error().stack?.split('\n') ?? [];
+  for (const frame of frames) {
+    const [, file, line, column] = frame.match(/\((.+):(\d+):(\d+)\)$/) ?? [];
+    if (file && file !== 'test') return `${file}:${line}:${column}`;
+  }
+}
 
 function parseOptionsArgs(args) {
   // Check if enough arguments are specified.
@@ -215,7 +291,7 @@ function test(...params) {
   }
 
   // Hack: attach options to the test function.
-  Object.assign(testFn, options);
+  Object.assign(testFn, options, { location: callerLocation() });
 
   mainRunner.test(description, testFn);
 }
@@ -1,9 +1,10 @@
+use crate::js_bundle::media_types::MediaType;
 use crate::js_bundle::modules::ModulePath;
 use crate::js_bundle::modules::ModuleSource;
 use crate::js_bundle::modules::CORE_MODULES;
-use crate::js_bundle::transpilers::TypeScript;
 use anyhow::Result;
 use anyhow::{anyhow, bail};
+use base64::prelude::*;
 use colored::*;
 use lazy_static::lazy_static;
 use path_absolutize::*;
@@ -13,18 +14,171 @@ use sha::utils::Digest;
 use sha::utils::DigestExt;
 use std::env;
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use url::Url;
 
 /// Defines the interface of a module loader.
 pub trait ModuleLoader {
-    fn load(&self, specifier: &str) -> Result<ModuleSource>;
+    /// Loads a module's source along with the media type it is parsed as.
+    /// TypeScript and JSX are left to the bundler, other kinds of modules
+    /// (JSON, text, WebAssembly...) are wrapped into JavaScript.
+    fn load(&self, specifier: &str) -> Result<(ModuleSource, MediaType)>;
+    /// Loads the raw contents of a module, without any preprocessing.
+    fn load_bytes(&self, specifier: &str) -> Result<Vec<u8>>;
     fn resolve(&self, base: Option<&str>, specifier: &str) -> Result<ModulePath>;
 }
 
 static EXTENSIONS: &[&str] = &["js", "jsx", "ts", "tsx", "json", "wasm"];
 
+/// Turns raw bytes into a module source, based on their media type.
+fn preprocess(
+    specifier: &str,
+    media_type: MediaType,
+    bytes: Vec<u8>,
+) -> Result<(ModuleSource, MediaType)> {
+    let source = match media_type {
+        MediaType::Wasm => wrap_wasm(&bytes),
+        MediaType::Json => wrap_json(specifier, &String::from_utf8(bytes)?)?,
+        MediaType::Text => wrap_text(&String::from_utf8(bytes)?),
+        MediaType::Binary => wrap_bytes(&bytes),
+        _ => return Ok((String::from_utf8(bytes)?, media_type)),
+    };
+    Ok((source, MediaType::JavaScript))
+}
+
+lazy_static! {
+    // Identifiers which can be exported by name from a JSON module.
+    static ref IDENTIFIER_REGEX: Regex = Regex::new(r"^[A-Za-z_$][A-Za-z0-9_$]*$").unwrap();
+}
+
+static RESERVED_WORDS: &[&str] = &[
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "arguments",
+    "eval",
+];
+
+/// Wraps JSON data into an ES module.
+///
+/// Top-level keys that are valid identifiers are also exported by name, so
+/// unused parts of the document can be tree-shaken. Keys keep the document's
+/// order, as with `JSON.parse`.
+pub fn wrap_json(specifier: &str, source: &str) -> Result<ModuleSource> {
+    let value: serde_json::Value = serde_json::from_str(source)
+        .map_err(|e| anyhow!("Invalid JSON module \"{specifier}\": {e}"))?;
+
+    let map = match value {
+        serde_json::Value::Object(map) => map,
+        value => return Ok(format!("export default {value};\n")),
+    };
+
+    let mut module = String::new();
+    let mut props = vec![];
+    for (key, value) in map {
+        if IDENTIFIER_REGEX.is_match(&key) && !RESERVED_WORDS.contains(&key.as_str()) {
+            module.push_str(&format!("export const {key} = {value};\n"));
+            props.push(key);
+        } else {
+            props.push(format!("{}: {value}", serde_json::Value::String(key)));
+        }
+    }
+    module.push_str(&format!("export default {{ {} }};\n", props.join(", ")));
+
+    Ok(module)
+}
+
+/// Wraps text into an ES module exporting it as a string.
+pub fn wrap_text(source: &str) -> ModuleSource {
+    let source = serde_json::Value::String(source.into());
+    format!("export default {source};\n")
+}
+
+/// Decodes a base64 string into a `Uint8Array`, for embedding binary data.
+static BASE64_DECODER: &str = r#"function decode(text) {
+  const alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  const lookup = new Uint8Array(128);
+  for (let i = 0; i < alphabet.length; i++) lookup[alphabet.charCodeAt(i)] = i;
+  const padding = text.endsWith("==") ? 2 : text.endsWith("=") ? 1 : 0;
+  const bytes = new Uint8Array((text.length / 4) * 3 - padding);
+  for (let i = 0, j = 0; i < text.length; i += 4) {
+    const n = (lookup[text.charCodeAt(i)] << 18) | (lookup[text.charCodeAt(i + 1)] << 12) |
+      (lookup[text.charCodeAt(i + 2)] << 6) | lookup[text.charCodeAt(i + 3)];
+    bytes[j++] = (n >> 16) & 255;
+    if (j < bytes.length) bytes[j++] = (n >> 8) & 255;
+    if (j < bytes.length) bytes[j++] = n & 255;
+  }
+  return bytes;
+}
+"#;
+
+/// Wraps binary data into an ES module exporting it as a `Uint8Array`.
+pub fn wrap_bytes(bytes: &[u8]) -> ModuleSource {
+    let data = BASE64_STANDARD.encode(bytes);
+    format!("const data = \"{data}\";\n{BASE64_DECODER}export default decode(data);\n")
+}
+
+/// Wraps a WebAssembly binary into an ES module, embedding it as base64.
+///
+/// The default export instantiates the binary with the given imports and
+/// resolves to the `WebAssembly.Instance`.
+pub fn wrap_wasm(bytes: &[u8]) -> ModuleSource {
+    let data = BASE64_STANDARD.encode(bytes);
+    format!(
+        r#"const data = "{data}";
+{BASE64_DECODER}export default async function init(imports = {{}}) {{
+  const {{ instance }} = await WebAssembly.instantiate(decode(data), imports);
+  return instance;
+}}
+"#
+    )
+}
+
 #[derive(Default)]
 pub struct FsModuleLoader;
 
@@ -34,60 +188,32 @@ impl FsModuleLoader {
         path.into_os_string().into_string().unwrap()
     }
 
-    /// Checks if path is a JSON file.
-    fn is_json_import(&self, path: &Path) -> bool {
-        match path.extension() {
-            Some(value) => value == "json",
-            None => false,
-        }
-    }
-
-    /// Wraps JSON data into an ES module (using v8's built in objects).
-    fn wrap_json(&self, source: &str) -> String {
-        format!("export default JSON.parse(`{source}`);")
-    }
-
-    /// Loads contents from a file.
+    /// Loads contents from a file, wrapping non-JS files into modules.
     fn load_source(&self, path: &Path) -> Result<ModuleSource> {
-        let source = fs::read_to_string(path)?;
-        let source = match self.is_json_import(path) {
-            true => self.wrap_json(source.as_str()),
-            false => source,
-        };
-
-        Ok(source)
-    }
-
-    /// Loads import as file.
-    fn load_as_file(&self, path: &Path) -> Result<ModuleSource> {
-        // 1. Check if path is already a valid file.
-        if path.is_file() {
-            return self.load_source(path);
+        let specifier = path.to_string_lossy();
+        match MediaType::from_path(&specifier) {
+            MediaType::Wasm => Ok(wrap_wasm(&fs::read(path)?)),
+            MediaType::Json => wrap_json(&specifier, &fs::read_to_string(path)?),
+            MediaType::Text => Ok(wrap_text(&fs::read_to_string(path)?)),
+            MediaType::Binary => Ok(wrap_bytes(&fs::read(path)?)),
+            _ => Ok(fs::read_to_string(path)?),
         }
-
-        // 2. Check if we need to add an extension.
-        if path.extension().is_none() {
-            for ext in EXTENSIONS {
-                let path = &path.with_extension(ext);
-                if path.is_file() {
-                    return self.load_source(path);
-                }
-            }
-        }
-
-        // 3. Bail out with an error.
-        bail!(format!("Module not found \"{}\"", path.display()));
     }
 
-    /// Loads import as directory using the 'index.[ext]' convention.
-    fn load_as_directory(&self, path: &Path) -> Result<ModuleSource> {
-        for ext in EXTENSIONS {
-            let path = &path.join(format!("index.{ext}"));
-            if path.is_file() {
-                return self.load_source(path);
-            }
+    /// Finds the file an import refers to: the path itself, the path with
+    /// one of the known extensions, or the directory's `index.[ext]` file.
+    pub fn find_file(&self, path: &Path) -> Option<PathBuf> {
+        if path.is_file() {
+            return Some(path.to_path_buf());
         }
-        bail!(format!("Module not found \"{}\"", path.display()));
+        let with_extension = EXTENSIONS
+            .iter()
+            .filter(|_| path.extension().is_none())
+            .map(|ext| path.with_extension(ext));
+        let index = EXTENSIONS
+            .iter()
+            .map(|ext| path.join(format!("index.{ext}")));
+        with_extension.chain(index).find(|path| path.is_file())
     }
 }
 
@@ -114,12 +240,14 @@ impl ModuleLoader for FsModuleLoader {
         bail!(format!("Module not found \"{specifier}\""));
     }
 
-    fn load(&self, specifier: &str) -> Result<ModuleSource> {
+    fn load(&self, specifier: &str) -> Result<(ModuleSource, MediaType)> {
         // Load source.
         let path = Path::new(specifier);
-        let maybe_source = self
-            .load_as_file(path)
-            .or_else(|_| self.load_as_directory(path));
+        // Surface errors (e.g. invalid JSON) of files which do exist.
+        let maybe_source = match self.find_file(path) {
+            Some(file) => Some(self.load_source(&file)?),
+            None => None,
+        };
 
         // Append default extension (if none specified).
         let path = match path.extension() {
@@ -128,17 +256,22 @@ impl ModuleLoader for FsModuleLoader {
         };
 
         let source = match maybe_source {
-            Ok(source) => source,
-            Err(_) => bail!(format!("Module not found \"{}\"", path.display())),
+            Some(source) => source,
+            None => bail!(format!("Module not found \"{}\"", path.display())),
         };
 
-        let path_extension = path.extension().unwrap().to_str().unwrap();
-        let fname = path.to_str();
+        // Non-JavaScript modules were wrapped by `load_source`.
+        let media_type = match MediaType::from_path(&path.to_string_lossy()) {
+            media_type @ (MediaType::TypeScript | MediaType::Tsx | MediaType::Jsx) => media_type,
+            _ => MediaType::JavaScript,
+        };
+        Ok((source, media_type))
+    }
 
-        // Use a preprocessor if necessary.
-        match path_extension {
-            "ts" => TypeScript::compile(fname, &source).map_err(|e| anyhow!("{}", e.to_string())),
-            _ => Ok(source),
+    fn load_bytes(&self, specifier: &str) -> Result<Vec<u8>> {
+        match fs::read(specifier) {
+            Ok(bytes) => Ok(bytes),
+            Err(_) => bail!(format!("Module not found \"{specifier}\"")),
         }
     }
 }
@@ -148,7 +281,7 @@ lazy_static! {
     pub static ref CACHE_DIR: PathBuf = if cfg!(debug_assertions) {
         PathBuf::from(".cache")
     } else {
-        dirs::home_dir().unwrap().join(".dune/cache")
+        dirs::home_dir().unwrap().join(".dino/cache")
     };
 }
 
@@ -181,7 +314,21 @@ impl ModuleLoader for UrlModuleLoader {
         bail!("Base is not a valid URL");
     }
 
-    fn load(&self, specifier: &str) -> Result<ModuleSource> {
+    fn load(&self, specifier: &str) -> Result<(ModuleSource, MediaType)> {
+        let (source, media_type) = self.fetch(specifier)?;
+
+        // Use a preprocessor if necessary.
+        preprocess(specifier, media_type, source)
+    }
+
+    fn load_bytes(&self, specifier: &str) -> Result<Vec<u8>> {
+        Ok(self.fetch(specifier)?.0)
+    }
+}
+
+impl UrlModuleLoader {
+    /// Downloads a module (or reads it from cache) along with its media type.
+    fn fetch(&self, specifier: &str) -> Result<(Vec<u8>, MediaType)> {
         // Create the cache directory.
         if fs::create_dir_all(CACHE_DIR.as_path()).is_err() {
             bail!("Failed to create module caching directory");
@@ -189,38 +336,33 @@ impl ModuleLoader for UrlModuleLoader {
 
         // Hash URL using sha1.
         let hash = Sha1::default().digest(specifier.as_bytes()).to_hex();
-        let module_path = CACHE_DIR.join(hash);
+        let module_path = CACHE_DIR.join(&hash);
+        let media_type_path = CACHE_DIR.join(format!("{hash}.media_type"));
 
         if !self.skip_cache {
-            // Check cache, and load file.
-            if module_path.is_file() {
-                let source = fs::read_to_string(&module_path).unwrap();
-                return Ok(source);
+            // Check cache, and load file along with its recorded media type.
+            if module_path.is_file() && media_type_path.is_file() {
+                let source = fs::read(&module_path)?;
+                let media_type = fs::read_to_string(&media_type_path)?.parse()?;
+                return Ok((source, media_type));
             }
         }
 
         println!("{} {}", "Downloading".green(), specifier);
 
         // Download file and, save it to cache.
-        let source = match ureq::get(specifier).call()?.into_string() {
-            Ok(source) => source,
-            Err(_) => bail!(format!("Module not found \"{specifier}\"")),
-        };
-
-        // Use a preprocessor if necessary.
-        let source = match (
-            specifier.ends_with(".wasm"),
-            specifier.ends_with(".jsx"),
-            specifier.ends_with(".ts"),
-            specifier.ends_with(".tsx"),
-        ) {
-            (_, _, true, _) => TypeScript::compile(Some(specifier), &source)?,
-            _ => source,
-        };
+        let response = ureq::get(specifier).call()?;
+        let media_type = MediaType::from_content_type(specifier, response.header("content-type"));
+        let mut source = vec![];
+        if response.into_reader().read_to_end(&mut source).is_err() {
+            bail!(format!("Module not found \"{specifier}\""));
+        }
 
+        // Cache the original source, so offline loads transpile identically.
         fs::write(&module_path, &source)?;
+        fs::write(&media_type_path, media_type.as_str())?;
 
-        Ok(source)
+        Ok((source, media_type))
     }
 }
 
@@ -234,10 +376,15 @@ impl ModuleLoader for CoreModuleLoader {
             None => bail!(format!("Module not found \"{specifier}\"")),
         }
     }
-    fn load(&self, specifier: &str) -> Result<ModuleSource> {
+    fn load(&self, specifier: &str) -> Result<(ModuleSource, MediaType)> {
         // Since any errors will be caught at the resolve stage, we can
         // go ahead an unwrap the value with no worries.
-        Ok(CORE_MODULES.get(specifier).unwrap().to_string())
+        let source = CORE_MODULES.get(specifier).unwrap().to_string();
+        Ok((source, MediaType::JavaScript))
+    }
+
+    fn load_bytes(&self, specifier: &str) -> Result<Vec<u8>> {
+        Ok(self.load(specifier)?.0.into_bytes())
     }
 }
 
@@ -327,10 +474,51 @@ mod tests {
             let source = loader.load(&path);
 
             assert!(source.is_ok());
-            assert_eq!(source.unwrap(), SRC);
+            assert_eq!(source.unwrap(), (SRC.into(), MediaType::JavaScript));
+        }
+    }
+
+    #[test]
+    fn test_load_fs_jsx_imports() {
+        // Crate temp dir.
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+
+        let tests = vec![
+            ("app.tsx", "export const app = <div />;", MediaType::Tsx),
+            ("page.jsx", "export const page = <p />;", MediaType::Jsx),
+            ("data.json", "{}", MediaType::JavaScript),
+        ];
+
+        // Run tests.
+        let loader = FsModuleLoader;
+
+        for (file, source, media_type) in tests {
+            let path = temp_dir.child(file);
+            path.write_str(source).unwrap();
+
+            let loaded = loader.load(&path.display().to_string()).unwrap();
+            assert_eq!(loaded.1, media_type);
         }
     }
 
+    #[test]
+    fn test_wrap_json() {
+        let source = wrap_json("data.json", r#"{"name": "dino", "class": 1, "a-b": [`x`]}"#);
+        assert!(source.is_err());
+
+        let source = wrap_json(
+            "data.json",
+            r#"{"name": "dino", "class": 1, "a-b": ["`${x}`"]}"#,
+        );
+        assert_eq!(
+            source.unwrap(),
+            "export const name = \"dino\";\nexport default { name, \"class\": 1, \"a-b\": [\"`${x}`\"] };\n"
+        );
+
+        let source = wrap_json("data.json", "[1, 2]");
+        assert_eq!(source.unwrap(), "export default [1,2];\n");
+    }
+
     #[test]
     fn test_resolve_url_imports() {
         // Group of tests to be run.
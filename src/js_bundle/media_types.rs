@@ -0,0 +1,224 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use url::Url;
+
+/// The kind of source a module specifier points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    JavaScript,
+    Jsx,
+    TypeScript,
+    Tsx,
+    Json,
+    Wasm,
+    /// Text files imported as strings (HTML templates, SQL, ...).
+    Text,
+    /// Images and fonts, imported as bytes or as emitted asset files.
+    Binary,
+    Unknown,
+}
+
+impl MediaType {
+    /// Guesses the media type from the specifier's extension.
+    pub fn from_path(specifier: &str) -> Self {
+        // URLs may carry a query or a fragment after the extension.
+        let path = match Url::parse(specifier) {
+            Ok(url) if url.scheme() != "file" => url.path().to_string(),
+            _ => specifier.to_string(),
+        };
+
+        let extension = Path::new(&path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        match extension.as_deref() {
+            Some("js" | "mjs" | "cjs") => MediaType::JavaScript,
+            Some("jsx") => MediaType::Jsx,
+            Some("ts" | "mts" | "cts") => MediaType::TypeScript,
+            Some("tsx") => MediaType::Tsx,
+            Some("json") => MediaType::Json,
+            Some("wasm") => MediaType::Wasm,
+            Some("html" | "htm" | "txt" | "sql" | "md" | "css" | "csv") => MediaType::Text,
+            Some(
+                "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico" | "woff" | "woff2"
+                | "ttf" | "otf" | "eot",
+            ) => MediaType::Binary,
+            _ => MediaType::Unknown,
+        }
+    }
+
+    /// Decides the media type from a `Content-Type` header, falling back to
+    /// the specifier's extension when the header is missing or generic.
+    pub fn from_content_type(specifier: &str, content_type: Option<&str>) -> Self {
+        let from_path = MediaType::from_path(specifier);
+
+        // Drop parameters such as `; charset=utf-8`.
+        let mime = match content_type {
+            Some(value) => value.split(';').next().unwrap().trim().to_lowercase(),
+            None => return from_path,
+        };
+
+        match mime.as_str() {
+            "application/typescript"
+            | "application/x-typescript"
+            | "text/typescript"
+            | "text/x-typescript" => match from_path {
+                MediaType::Tsx => MediaType::Tsx,
+                _ => MediaType::TypeScript,
+            },
+            "text/tsx" => MediaType::Tsx,
+            "text/jsx" => MediaType::Jsx,
+            "application/javascript"
+            | "application/x-javascript"
+            | "application/ecmascript"
+            | "text/javascript"
+            | "text/ecmascript" => match from_path {
+                MediaType::Jsx => MediaType::Jsx,
+                _ => MediaType::JavaScript,
+            },
+            "application/json" | "text/json" => MediaType::Json,
+            "application/wasm" => MediaType::Wasm,
+            "text/html" | "text/markdown" | "text/css" | "text/csv" | "application/sql" => {
+                MediaType::Text
+            }
+            mime if mime.starts_with("image/") || mime.starts_with("font/") => MediaType::Binary,
+            _ => from_path,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::JavaScript => "javascript",
+            MediaType::Jsx => "jsx",
+            MediaType::TypeScript => "typescript",
+            MediaType::Tsx => "tsx",
+            MediaType::Json => "json",
+            MediaType::Wasm => "wasm",
+            MediaType::Text => "text",
+            MediaType::Binary => "binary",
+            MediaType::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value.trim() {
+            "javascript" => MediaType::JavaScript,
+            "jsx" => MediaType::Jsx,
+            "typescript" => MediaType::TypeScript,
+            "tsx" => MediaType::Tsx,
+            "json" => MediaType::Json,
+            "wasm" => MediaType::Wasm,
+            "text" => MediaType::Text,
+            "binary" => MediaType::Binary,
+            "unknown" => MediaType::Unknown,
+            _ => bail!("Unknown media type \"{value}\""),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_type_from_path_should_work() {
+        let tests = vec![
+            ("/dev/main.ts", MediaType::TypeScript),
+            ("/dev/main.mts", MediaType::TypeScript),
+            ("/dev/app.tsx", MediaType::Tsx),
+            ("/dev/app.jsx", MediaType::Jsx),
+            ("/dev/lib.mjs", MediaType::JavaScript),
+            ("/dev/data.json", MediaType::Json),
+            ("https://esm.sh/lib.wasm", MediaType::Wasm),
+            ("/dev/templates/index.html", MediaType::Text),
+            ("/dev/queries/users.sql", MediaType::Text),
+            ("/dev/assets/logo.PNG", MediaType::Binary),
+            ("/dev/assets/inter.woff2", MediaType::Binary),
+            ("https://esm.sh/app.tsx?target=es2022", MediaType::Tsx),
+            ("https://esm.sh/react", MediaType::Unknown),
+        ];
+
+        for (specifier, expected) in tests {
+            assert_eq!(MediaType::from_path(specifier), expected, "{specifier}");
+        }
+    }
+
+    #[test]
+    fn media_type_from_content_type_should_work() {
+        let tests = vec![
+            (
+                "https://esm.sh/react",
+                Some("application/typescript; charset=utf-8"),
+                MediaType::TypeScript,
+            ),
+            (
+                "https://esm.sh/app.tsx",
+                Some("text/typescript"),
+                MediaType::Tsx,
+            ),
+            (
+                "https://esm.sh/app.jsx",
+                Some("text/javascript"),
+                MediaType::Jsx,
+            ),
+            (
+                "https://esm.sh/react",
+                Some("application/javascript"),
+                MediaType::JavaScript,
+            ),
+            (
+                "https://esm.sh/lib.ts",
+                Some("text/plain"),
+                MediaType::TypeScript,
+            ),
+            ("https://esm.sh/lib.ts", None, MediaType::TypeScript),
+            (
+                "https://esm.sh/lib",
+                Some("application/wasm"),
+                MediaType::Wasm,
+            ),
+        ];
+
+        for (specifier, content_type, expected) in tests {
+            assert_eq!(
+                MediaType::from_content_type(specifier, content_type),
+                expected,
+                "{specifier}"
+            );
+        }
+    }
+
+    #[test]
+    fn media_type_should_round_trip() {
+        for media_type in [
+            MediaType::JavaScript,
+            MediaType::Jsx,
+            MediaType::TypeScript,
+            MediaType::Tsx,
+            MediaType::Json,
+            MediaType::Wasm,
+            MediaType::Text,
+            MediaType::Binary,
+            MediaType::Unknown,
+        ] {
+            assert_eq!(
+                media_type.to_string().parse::<MediaType>().unwrap(),
+                media_type
+            );
+        }
+    }
+}
@@ -1,14 +1,37 @@
+mod analyzer;
+mod coverage;
 mod loaders;
+mod media_types;
 mod modules;
+mod prefetch;
 mod transpilers;
 
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub use analyzer::{Analysis, ExportAnalysis, ModuleAnalysis};
+pub use coverage::{BranchLocation, CoverageCounters, CoverageMap, COVERAGE_GLOBAL};
+
+use analyzer::Analyzer;
+use coverage::instrument;
+use media_types::MediaType;
+pub use modules::import_hash;
 
 use modules::load_import;
+use modules::load_import_bytes;
 use modules::resolve_import;
 use modules::ImportMap;
-use modules::CORE_MODULES;
+use modules::ImportType;
+pub(crate) use modules::CORE_MODULES;
+use prefetch::prefetch_urls;
+use transpilers::parse_module;
+pub use transpilers::{compile_script, Script, MODULES_GLOBAL};
 
 use swc_atoms::js_word;
 use swc_atoms::JsWord;
@@ -19,39 +42,219 @@ use swc_bundler::ModuleData;
 use swc_bundler::ModuleRecord;
 use swc_bundler::ModuleType;
 use swc_bundler::Resolve;
-use swc_common::errors::ColorConfig;
-use swc_common::errors::Handler;
+use swc_common::chain;
+use swc_common::collections::AHashMap;
+use swc_common::comments::CommentKind;
+use swc_common::comments::Comments;
+use swc_common::comments::SingleThreadedComments;
+use swc_common::pass::Optional;
+use swc_common::pass::Repeat;
+use swc_common::source_map::LineCol;
+use swc_common::BytePos;
 use swc_common::FileName;
 use swc_common::Globals;
+use swc_common::Mark;
 use swc_common::Span;
+use swc_common::SyntaxContext;
+use swc_common::DUMMY_SP;
+use swc_common::GLOBALS;
 use swc_common::{sync::Lrc, FilePathMapping, SourceMap};
 use swc_ecma_ast::Bool;
 use swc_ecma_ast::EsVersion;
+use swc_ecma_ast::ExportAll;
 use swc_ecma_ast::Expr;
 use swc_ecma_ast::Ident;
+use swc_ecma_ast::ImportDecl;
 use swc_ecma_ast::KeyValueProp;
 use swc_ecma_ast::Lit;
 use swc_ecma_ast::MemberExpr;
 use swc_ecma_ast::MemberProp;
 use swc_ecma_ast::MetaPropExpr;
 use swc_ecma_ast::MetaPropKind;
+use swc_ecma_ast::Module;
+use swc_ecma_ast::ModuleDecl;
+use swc_ecma_ast::ModuleItem;
+use swc_ecma_ast::NamedExport;
+use swc_ecma_ast::ObjectLit;
+use swc_ecma_ast::Program;
+use swc_ecma_ast::Prop;
 use swc_ecma_ast::PropName;
+use swc_ecma_ast::PropOrSpread;
 use swc_ecma_ast::Str;
 use swc_ecma_codegen::text_writer::JsWriter;
 use swc_ecma_codegen::Emitter;
 
 use anyhow::{Error, Result};
+use clap::ValueEnum;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
 use swc_ecma_loader::resolve::Resolution;
-use swc_ecma_parser::parse_file_as_module;
+use swc_ecma_minifier::optimize;
+use swc_ecma_minifier::option::CompressOptions;
+use swc_ecma_minifier::option::ExtraOptions;
+use swc_ecma_minifier::option::MangleOptions;
+use swc_ecma_minifier::option::MinifyOptions;
+use swc_ecma_parser::parse_file_as_expr;
 use swc_ecma_parser::EsSyntax;
 use swc_ecma_parser::Syntax;
+use swc_ecma_transforms_base::feature::FeatureFlag;
+use swc_ecma_transforms_base::fixer::fixer;
+use swc_ecma_transforms_base::helpers::inject_helpers;
+use swc_ecma_transforms_base::helpers::Helpers;
+use swc_ecma_transforms_base::helpers::HELPERS;
+use swc_ecma_transforms_base::hygiene::hygiene;
+use swc_ecma_transforms_base::resolver;
+use swc_ecma_transforms_compat as compat;
+use swc_ecma_transforms_module::common_js;
+use swc_ecma_transforms_module::import_analysis::import_analyzer;
+use swc_ecma_transforms_module::util::ImportInterop;
+use swc_ecma_transforms_optimization::inline_globals2;
+use swc_ecma_transforms_optimization::simplify::dead_branch_remover;
+use swc_ecma_transforms_optimization::simplify::expr_simplifier;
+use swc_ecma_transforms_optimization::GlobalExprMap;
+use swc_ecma_utils::NodeIgnoringSpan;
+use swc_ecma_visit::FoldWith;
+use swc_ecma_visit::VisitMut;
+use swc_ecma_visit::VisitMutWith;
+use swc_node_comments::SwcComments;
+
+lazy_static! {
+    /// Comments telling the minifier that a call has no side effects.
+    static ref ANNOTATION_REGEX: Regex =
+        Regex::new(r"^\s*[@#]__(PURE|NO_SIDE_EFFECTS)__\s*$").unwrap();
+}
+
+/// Global replaced with the build's metadata, see `Options::metadata`.
+pub const BUILD_METADATA_GLOBAL: &str = "__DINO_BUILD__";
+
+/// Bumped when what goes into the transpile cache changes, e.g. comments.
+const TRANSPILE_CACHE_FORMAT: u32 = 2;
 
 #[derive(Debug)]
 pub struct Options {
     pub skip_cache: bool,
     pub minify: bool,
     pub import_map: Option<ImportMap>,
-    pub module: ModuleType,
+    pub format: OutputFormat,
+    /// When set, images and fonts are copied into this directory under a
+    /// content hash and imported as their URL, instead of being inlined as
+    /// `Uint8Array`s.
+    pub asset_dir: Option<PathBuf>,
+    /// Global expressions (e.g. `process.env.NODE_ENV`, `__VERSION__`) replaced
+    /// with JS expressions at build time. Branches made constant by the
+    /// replacements are removed.
+    pub define: BTreeMap<String, String>,
+    /// Runs swc's minifier (compress and mangle) over the bundle. Unlike
+    /// `minify`, which only strips whitespace, this rewrites the code.
+    pub minifier: Option<MinifierOptions>,
+    /// Reports the module graph, see [`Analysis`].
+    pub analyze: bool,
+    /// Generates a source map alongside the bundle.
+    pub source_map: bool,
+    /// ES version the code is emitted for. Syntax newer than the target
+    /// (classes, async functions, optional chaining, private fields...) is
+    /// compiled down to it.
+    pub target: EsVersion,
+    /// Where legal comments (`/*! ... */`, `@license`, `@preserve`) of the
+    /// bundled modules go. Other comments are dropped, except for `@__PURE__`
+    /// style annotations, which the minifier relies on.
+    pub legal_comments: LegalComments,
+    /// Identifies the build. Available to the bundle as the `__DINO_BUILD__`
+    /// global and to `banner` and `footer`.
+    pub metadata: Option<BuildMetadata>,
+    /// Text put at the top of the bundle, e.g. a comment naming the project.
+    /// `{name}`, `{version}`, `{commit}`, `{built_at}` and `{dino_version}`
+    /// are replaced with the build's metadata. Defaults to a short notice when
+    /// the output is not minified.
+    pub banner: Option<String>,
+    /// Text put at the end of the bundle, with the same replacements as
+    /// `banner`.
+    pub footer: Option<String>,
+    /// Modules imported ahead of the entry, e.g. polyfills for the APIs the
+    /// target engine lacks. Resolved like the entry's own imports.
+    pub polyfills: Vec<String>,
+    /// Caches transpiled TypeScript and JSX modules here, keyed by their
+    /// contents, so that unchanged files are not transpiled again. Unused
+    /// with `source_map`, `analyze` or `coverage`, which need the sources.
+    pub cache_dir: Option<PathBuf>,
+    /// Counts the statements and branches run in the project's own modules,
+    /// see `BundleOutput::coverage`. Instrumented modules aren't cached.
+    pub coverage: bool,
+}
+
+/// Module format of a bundle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// A function expression evaluating to an object of the entry's exports.
+    #[default]
+    Iife,
+    /// An ES module with the entry's exports as named exports.
+    Esm,
+    /// A CommonJS module assigning the entry's exports to `exports`.
+    Cjs,
+}
+
+/// Describes a build, see `Options::metadata`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BuildMetadata {
+    pub name: String,
+    pub version: Option<String>,
+    /// Abbreviated hash of the commit the project was built from.
+    pub commit: Option<String>,
+    /// RFC 3339 timestamp.
+    pub built_at: String,
+}
+
+impl BuildMetadata {
+    /// Replaces the placeholders listed in `Options::banner`.
+    pub fn render(&self, template: &str) -> String {
+        template
+            .replace("{name}", &self.name)
+            .replace("{version}", self.version.as_deref().unwrap_or_default())
+            .replace("{commit}", self.commit.as_deref().unwrap_or_default())
+            .replace("{built_at}", &self.built_at)
+            .replace("{dino_version}", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+/// What to do with the legal comments of a bundle.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LegalComments {
+    /// Kept at the top of the bundle.
+    #[default]
+    Inline,
+    /// Left out of the bundle, see `BundleOutput::licenses`.
+    External,
+    /// Dropped.
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MinifierOptions {
+    /// Drops dead code, folds constants and simplifies expressions.
+    pub compress: bool,
+    /// Shortens local names.
+    pub mangle: bool,
+    /// Keeps function names, so they show up in stack traces.
+    pub keep_fn_names: bool,
+    /// Keeps class names, so they show up in stack traces.
+    pub keep_class_names: bool,
+}
+
+impl Default for MinifierOptions {
+    fn default() -> Self {
+        Self {
+            compress: true,
+            mangle: true,
+            keep_fn_names: false,
+            keep_class_names: false,
+        }
+    }
 }
 
 impl Default for Options {
@@ -60,7 +263,20 @@ impl Default for Options {
             skip_cache: true,
             minify: true,
             import_map: Default::default(),
-            module: ModuleType::Iife,
+            format: OutputFormat::Iife,
+            asset_dir: None,
+            define: Default::default(),
+            minifier: None,
+            analyze: false,
+            source_map: false,
+            target: EsVersion::latest(),
+            polyfills: vec![],
+            metadata: None,
+            banner: None,
+            footer: None,
+            legal_comments: LegalComments::Inline,
+            cache_dir: None,
+            coverage: false,
         }
     }
 }
@@ -68,6 +284,21 @@ impl Default for Options {
 struct Loader<'s> {
     cm: Lrc<SourceMap>,
     options: &'s Options,
+    entry: &'s str,
+    /// Annotations kept for the minifier and the output.
+    comments: &'s SwcComments,
+    /// Legal comments of each module, see `BundleOutput::licenses`.
+    licenses: &'s Mutex<BTreeMap<String, Vec<String>>>,
+    define: GlobalExprMap,
+    analyzer: Option<&'s Analyzer>,
+    /// File names of the assets copied into `Options::asset_dir`.
+    assets: &'s Mutex<BTreeSet<String>>,
+    /// Loaded modules, see `BundleOutput::inputs`.
+    inputs: &'s Mutex<BTreeMap<String, String>>,
+    /// Instrumented modules, see `BundleOutput::coverage`.
+    coverage: &'s Mutex<BTreeMap<String, CoverageMap>>,
+    /// URL modules downloaded ahead of bundling, see [`prefetch_urls`].
+    prefetched: HashSet<String>,
 }
 
 impl<'s> Load for Loader<'s> {
@@ -79,58 +310,283 @@ impl<'s> Load for Loader<'s> {
         };
 
         // Try load the module's source-code.
-        let source = load_import(&specifier, self.options.skip_cache)?;
-        let path = Lrc::new(FileName::Real(specifier.into()));
-        let fm = self.cm.new_source_file(path, source);
+        let (path, import_type) = ImportType::untag(&specifier)?;
+        let hash = import_hash(&specifier)?;
+        self.inputs.lock().unwrap().insert(specifier.clone(), hash);
+        let (source, media_type, cache) = match (
+            &self.options.asset_dir,
+            import_type,
+            MediaType::from_path(path),
+        ) {
+            (Some(dir), ImportType::JavaScript, MediaType::Binary) => {
+                let file_name =
+                    emit_asset(dir, path, &load_import_bytes(path, self.skip_cache(path))?)?;
+                let source = format!("export default \"./{file_name}\";\n");
+                self.assets.lock().unwrap().insert(file_name);
+                (source, MediaType::JavaScript, None)
+            }
+            (_, import_type, media_type) => self.load_source(path, import_type, media_type)?,
+        };
+        let source_size = source.len();
+        let file_name = Lrc::new(FileName::Real(specifier.clone().into()));
+        let fm = self.cm.new_source_file(file_name, source);
+
+        // Parse the source into an SWC module, compiling TypeScript and JSX away.
+        let comments = SwcComments::default();
+        let mut module = parse_module(&self.cm, &fm, media_type, Some(&comments))?;
+        if let Some(file) = cache {
+            fs::create_dir_all(file.parent().unwrap())?;
+            let source = emit(
+                &self.cm,
+                &module,
+                self.options,
+                Some(&copy_comments(&comments)),
+                None,
+            )?;
+            fs::write(file, source)?;
+        }
+
+        let licenses = sort_comments(&comments, self.comments);
+        if !licenses.is_empty() {
+            self.licenses
+                .lock()
+                .unwrap()
+                .insert(specifier.clone(), licenses);
+        }
+
+        if specifier == self.entry {
+            prepend_imports(&mut module, &self.options.polyfills);
+        }
+
+        // Carry import attributes over to the resolver.
+        let mut attributes = ImportAttributes::default();
+        module.visit_mut_with(&mut attributes);
+        if let Some(e) = attributes.error {
+            return Err(e.context(format!("Invalid import attributes in \"{}\"", fm.name)));
+        }
+
+        // Replace defined globals and drop the branches they make constant.
+        if !self.define.is_empty() {
+            let unresolved_mark = Mark::new();
+            module.visit_mut_with(&mut ClearMarks);
+            module = module
+                .fold_with(&mut resolver(unresolved_mark, Mark::new(), false))
+                .fold_with(&mut inline_globals2(
+                    Default::default(),
+                    Default::default(),
+                    self.define.clone(),
+                    Default::default(),
+                ))
+                .fold_with(&mut Repeat::new(chain!(
+                    expr_simplifier(unresolved_mark, Default::default()),
+                    dead_branch_remover(unresolved_mark)
+                )));
+        }
+
+        if let Some(analyzer) = self.analyzer {
+            analyzer.record_module(&specifier, source_size, &module);
+        }
+
+        if self.options.coverage && is_project_module(path, import_type) {
+            let map = instrument(&self.cm, path, &mut module);
+            self.coverage.lock().unwrap().insert(path.to_string(), map);
+        }
+
+        Ok(ModuleData {
+            fm,
+            module,
+            helpers: Default::default(),
+        })
+    }
+}
 
-        let handler =
-            Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(self.cm.clone()));
+impl<'s> Loader<'s> {
+    /// Loads a module, reusing the cached output when a TypeScript or JSX file
+    /// was transpiled before. On a cache miss, also returns the file the
+    /// transpiled module should be saved to.
+    fn load_source(
+        &self,
+        path: &str,
+        import_type: ImportType,
+        media_type: MediaType,
+    ) -> Result<(String, MediaType, Option<PathBuf>)> {
+        let transpiled = matches!(
+            media_type,
+            MediaType::TypeScript | MediaType::Tsx | MediaType::Jsx
+        );
+        let options = self.options;
+        let cache = match (&options.cache_dir, import_type, fs::read(path)) {
+            // counters, source maps and the analyzer need the positions of
+            // the sources, which the transpiled output has lost
+            _ if options.coverage || options.source_map || options.analyze => None,
+            (Some(dir), ImportType::JavaScript, Ok(bytes)) if transpiled => {
+                // the output also depends on the options `emit` uses
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(
+                    format!(
+                        "{}\n{TRANSPILE_CACHE_FORMAT}\n{media_type}\n{}\n{}\n",
+                        env!("CARGO_PKG_VERSION"),
+                        options.minify,
+                        serde_json::to_string(&options.target)?,
+                    )
+                    .as_bytes(),
+                );
+                hasher.update(&bytes);
+                Some(dir.join(format!("{}.js", hasher.finalize())))
+            }
+            _ => None,
+        };
+
+        if let Some(source) = cache
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+        {
+            return Ok((source, MediaType::JavaScript, None));
+        }
+        let (source, media_type) = load_import(path, import_type, self.skip_cache(path))?;
+        Ok((source, media_type, cache))
+    }
 
-        // Parse JavaScript source into an SWC module.
-        let module = match parse_file_as_module(
+    /// URL modules downloaded ahead are read back from the download cache.
+    fn skip_cache(&self, path: &str) -> bool {
+        self.options.skip_cache && !self.prefetched.contains(path)
+    }
+}
+
+/// Whether a module is the project's own JavaScript or TypeScript, rather
+/// than a dependency or a URL import.
+fn is_project_module(path: &str, import_type: ImportType) -> bool {
+    import_type == ImportType::JavaScript
+        && matches!(
+            MediaType::from_path(path),
+            MediaType::JavaScript | MediaType::Jsx | MediaType::TypeScript | MediaType::Tsx
+        )
+        && Path::new(path).is_absolute()
+        && !path.contains("/node_modules/")
+}
+
+/// Parses `define` entries into expressions to be matched and inlined.
+fn parse_define(cm: &Lrc<SourceMap>, define: &BTreeMap<String, String>) -> Result<GlobalExprMap> {
+    let parse = |name: String, source: &str| -> Result<Box<Expr>> {
+        let fm = cm.new_source_file(Lrc::new(FileName::Custom(name)), source.into());
+        parse_file_as_expr(
             &fm,
             Syntax::Es(EsSyntax::default()),
             EsVersion::latest(),
             None,
             &mut vec![],
         )
-        .map_err(|e| e.into_diagnostic(&handler).emit())
-        {
-            Ok(module) => module,
-            Err(_) => std::process::exit(1),
-        };
+        .map_err(|e| Error::msg(format!("{:?}", e.kind().msg())))
+    };
 
-        Ok(ModuleData {
-            fm,
-            module,
-            helpers: Default::default(),
-        })
+    let mut map = AHashMap::default();
+    for (key, value) in define {
+        let target = parse(format!("define:{key}"), key)?;
+        if !matches!(*target, Expr::Ident(_) | Expr::Member(_)) {
+            return Err(Error::msg(format!(
+                "Invalid define \"{key}\": must be an identifier or a member expression"
+            )));
+        }
+        let value = parse(format!("define:{key}:value"), value)
+            .map_err(|e| e.context(format!("Invalid define value for \"{key}\"")))?;
+        map.insert(NodeIgnoringSpan::owned(*target), *value);
     }
+
+    Ok(Lrc::new(map))
+}
+
+/// Emitting takes the comments it prints, so they are copied when still needed.
+fn copy_comments(comments: &SwcComments) -> SwcComments {
+    let copy = SwcComments::default();
+    for (from, to) in [
+        (&comments.leading, &copy.leading),
+        (&comments.trailing, &copy.trailing),
+    ] {
+        for entry in from.iter() {
+            to.insert(*entry.key(), entry.value().clone());
+        }
+    }
+    copy
+}
+
+/// Moves the annotations among a module's comments into `annotations`, and
+/// returns its legal comments in source order.
+fn sort_comments(comments: &SwcComments, annotations: &SwcComments) -> Vec<String> {
+    let mut entries = vec![];
+    for (map, trailing) in [(&comments.leading, false), (&comments.trailing, true)] {
+        for entry in map.iter() {
+            for comment in entry.value() {
+                entries.push((*entry.key(), trailing, comment.clone()));
+            }
+        }
+    }
+    entries.sort_by_key(|(pos, ..)| *pos);
+
+    let mut licenses = vec![];
+    for (pos, trailing, comment) in entries {
+        let text = comment.text.as_str();
+        if text.starts_with('!') || text.contains("@license") || text.contains("@preserve") {
+            licenses.push(match comment.kind {
+                CommentKind::Block => format!("/*{text}*/"),
+                CommentKind::Line => format!("//{text}"),
+            });
+        } else if comment.kind == CommentKind::Block && ANNOTATION_REGEX.is_match(text) {
+            match trailing {
+                true => annotations.add_trailing(pos, comment),
+                false => annotations.add_leading(pos, comment),
+            }
+        }
+    }
+    licenses
+}
+
+/// Copies an asset into `dir` under a content-hashed name, and returns the
+/// name.
+fn emit_asset(dir: &Path, specifier: &str, bytes: &[u8]) -> Result<String> {
+    let path = Path::new(specifier);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let hash = blake3::hash(bytes).to_hex();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}.{}.{}", &hash[..8], ext.to_string_lossy()),
+        None => format!("{stem}.{}", &hash[..8]),
+    };
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(&file_name), bytes)?;
+
+    Ok(file_name)
 }
 
 struct Resolver<'a> {
     options: &'a Options,
+    analyzer: Option<&'a Analyzer>,
 }
 
 impl<'a> Resolve for Resolver<'a> {
     fn resolve(&self, base: &FileName, specifier: &str) -> Result<Resolution, Error> {
         // We only dealing with `Real` filenames.
+        let base_file = base;
         let base = match base {
-            FileName::Real(value) => value.to_str(),
+            FileName::Real(value) => value.to_str().map(ImportType::untag).transpose()?,
             _ => unreachable!(),
         };
 
-        // Try resolve the specifier.
+        // Try resolve the specifier, keeping its import type.
+        let (specifier, import_type) = ImportType::untag(specifier)?;
+        let path = resolve_import(
+            base.map(|(base, _)| base),
+            specifier,
+            true,
+            self.options.import_map.clone(),
+        )?;
+
+        let filename = FileName::Real(Path::new(&import_type.tag(&path)).to_path_buf());
+        if let Some(analyzer) = self.analyzer {
+            analyzer.record_import(base_file, &filename);
+        }
+
         Ok(Resolution {
-            filename: FileName::Real(
-                Path::new(&resolve_import(
-                    base,
-                    specifier,
-                    true,
-                    self.options.import_map.clone(),
-                )?)
-                .to_path_buf(),
-            ),
+            filename,
             slug: None,
         })
     }
@@ -146,7 +602,8 @@ impl swc_bundler::Hook for Hook {
     ) -> Result<Vec<KeyValueProp>, Error> {
         // Get filename as string.
         let file_name = module.file_name.to_string();
-        let file_name = resolve_import(None, &file_name, true, None)?;
+        let (file_name, _) = ImportType::untag(&file_name)?;
+        let file_name = resolve_import(None, file_name, true, None)?;
 
         // Compute .main and .url properties.
         Ok(vec![
@@ -177,15 +634,124 @@ impl swc_bundler::Hook for Hook {
     }
 }
 
+/// Moves the `type` of import attributes (`with { type: "json" }`) into the
+/// specifier, so the resolver and loader can honour it.
+#[derive(Default)]
+struct ImportAttributes {
+    error: Option<Error>,
+}
+
+impl ImportAttributes {
+    fn tag(&mut self, src: &mut Str, with: &mut Option<Box<ObjectLit>>) {
+        let Some(attributes) = with.take() else {
+            return;
+        };
+
+        for prop in attributes.props.iter() {
+            let PropOrSpread::Prop(prop) = prop else {
+                continue;
+            };
+            let Prop::KeyValue(KeyValueProp { key, value }) = &**prop else {
+                continue;
+            };
+            let key = match key {
+                PropName::Ident(ident) => ident.sym.to_string(),
+                PropName::Str(s) => s.value.to_string(),
+                _ => continue,
+            };
+            if key != "type" {
+                continue;
+            }
+
+            let import_type = match &**value {
+                Expr::Lit(Lit::Str(s)) => s.value.parse::<ImportType>(),
+                _ => Err(Error::msg("Import attribute \"type\" must be a string")),
+            };
+            match import_type {
+                Ok(import_type) => {
+                    src.value = import_type.tag(&src.value).into();
+                    src.raw = None;
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+impl VisitMut for ImportAttributes {
+    fn visit_mut_import_decl(&mut self, n: &mut ImportDecl) {
+        self.tag(&mut n.src, &mut n.with);
+    }
+
+    fn visit_mut_named_export(&mut self, n: &mut NamedExport) {
+        if let Some(src) = n.src.as_mut() {
+            self.tag(src, &mut n.with);
+        }
+    }
+
+    fn visit_mut_export_all(&mut self, n: &mut ExportAll) {
+        self.tag(&mut n.src, &mut n.with);
+    }
+}
+
+/// The result of bundling an entry point.
+#[derive(Debug)]
+pub struct BundleOutput {
+    pub code: String,
+    /// Size of the output before the minifier ran, when it is enabled.
+    pub unminified_size: Option<usize>,
+    /// Set when `Options::analyze` is.
+    pub analysis: Option<Analysis>,
+    /// Set when `Options::source_map` is.
+    pub source_map: Option<String>,
+    /// Assets copied into `Options::asset_dir`.
+    pub assets: Vec<String>,
+    /// Every module in the graph, with the hash of its contents, see
+    /// [`import_hash`].
+    pub inputs: BTreeMap<String, String>,
+    /// Legal comments of the bundled modules, also at the top of the code
+    /// when `Options::legal_comments` is `Inline`.
+    pub licenses: Vec<String>,
+    /// Instrumented modules by file, set when `Options::coverage` is. Their
+    /// counters are collected from the worker running the bundle.
+    pub coverage: BTreeMap<String, CoverageMap>,
+}
+
 pub fn run_bundle(entry: &str, options: &Options) -> Result<String> {
+    Ok(bundle(entry, options)?.code)
+}
+
+pub fn bundle(entry: &str, options: &Options) -> Result<BundleOutput> {
     // Create SWC globals and an LRC sourcemap.
     let globals = Globals::default();
     let cm = Lrc::new(SourceMap::new(FilePathMapping::empty()));
 
-    // NOTE: Core modules are built-in to dune's binary so there is no point to pollute
+    // NOTE: Core modules are built-in to dino's binary so there is no point to pollute
     // the bundle with extra code that the runtime can load anyway.
     let external_modules: Vec<JsWord> = CORE_MODULES.keys().map(|k| (*k).into()).collect();
 
+    let analyzer = options.analyze.then(Analyzer::default);
+    let assets = Mutex::default();
+    let inputs = Mutex::default();
+    let coverage = Mutex::default();
+    let comments = SwcComments::default();
+    let licenses = Mutex::<BTreeMap<String, Vec<String>>>::default();
+
+    let mut define = options.define.clone();
+    if let Some(metadata) = &options.metadata {
+        define
+            .entry(BUILD_METADATA_GLOBAL.to_string())
+            .or_insert(serde_json::to_string(metadata)?);
+    }
+
+    // Download URL imports up front, as the bundler loads modules one by one.
+    let entries: Vec<String> = std::iter::once(entry.to_string())
+        .chain(options.polyfills.iter().filter_map(|polyfill| {
+            resolve_import(Some(entry), polyfill, true, options.import_map.clone()).ok()
+        }))
+        .collect();
+    let prefetched = prefetch_urls(&entries, options.import_map.as_ref(), options.skip_cache);
+
     // Create the bundler.
     let mut bundler = Bundler::new(
         &globals,
@@ -193,14 +759,27 @@ pub fn run_bundle(entry: &str, options: &Options) -> Result<String> {
         Loader {
             cm: cm.clone(),
             options,
+            entry,
+            comments: &comments,
+            licenses: &licenses,
+            define: parse_define(&cm, &define)?,
+            analyzer: analyzer.as_ref(),
+            assets: &assets,
+            inputs: &inputs,
+            coverage: &coverage,
+            prefetched,
+        },
+        Resolver {
+            options,
+            analyzer: analyzer.as_ref(),
         },
-        Resolver { options },
         Config {
             external_modules,
             require: false,
-            module: match options.module {
-                ModuleType::Es => ModuleType::Es,
-                ModuleType::Iife => ModuleType::Iife,
+            // CommonJS is converted from the ES module output below.
+            module: match options.format {
+                OutputFormat::Iife => ModuleType::Iife,
+                OutputFormat::Esm | OutputFormat::Cjs => ModuleType::Es,
             },
             ..Default::default()
         },
@@ -218,42 +797,285 @@ pub fn run_bundle(entry: &str, options: &Options) -> Result<String> {
         .pop()
         .unwrap();
 
+    // The bundler keeps hold of the analyzer until dropped.
+    drop(bundler);
+    // Tree-shaking is judged on the bundle before the minifier rewrites it.
+    let bundled = analyzer.as_ref().map(|_| bundle.module.clone());
+
+    let mut module = bundle.module;
+    if options.target < EsVersion::latest() {
+        module = GLOBALS.set(&globals, || downlevel(module, options.target));
+    }
+    if options.format == OutputFormat::Cjs {
+        module = GLOBALS.set(&globals, || to_common_js(module));
+    }
+
+    let (module, unminified_size) = match &options.minifier {
+        Some(minifier) => {
+            let copy = copy_comments(&comments);
+            let unminified_size = emit(&cm, &module, options, Some(&copy), None)?.len();
+            let module = GLOBALS.set(&globals, || minify(&cm, module, minifier, &comments));
+            (module, Some(unminified_size))
+        }
+        None => (module, None),
+    };
+
+    // Build source from bytes.
+    let mut srcmap = vec![];
+    let mut source = emit(
+        &cm,
+        &module,
+        options,
+        Some(&comments),
+        (options.analyze || options.source_map).then_some(&mut srcmap),
+    )?;
+    let analysis = analyzer
+        .zip(bundled)
+        .map(|(analyzer, bundled)| analyzer.finish(&cm, entry, &bundled, &source, &srcmap));
+
+    // The same license can come with several modules.
+    let mut licenses: Vec<String> = licenses
+        .into_inner()
+        .unwrap()
+        .into_values()
+        .flatten()
+        .collect();
+    let mut seen = BTreeSet::new();
+    licenses.retain(|license| seen.insert(license.clone()));
+
+    let metadata = options.metadata.clone().unwrap_or_default();
+    let mut banner = String::new();
+    match &options.banner {
+        Some(template) => {
+            banner.push_str(&metadata.render(template));
+            banner.push('\n');
+        }
+        None if !options.minify => {
+            // Decorate output with the following messages.
+            banner.push_str(&format!("// Dino v{}\n", env!("CARGO_PKG_VERSION")));
+            banner.push_str("// It's not recommended to edit this code manually since it's generated by `dino build`\n\n");
+        }
+        None => {}
+    }
+    if options.legal_comments == LegalComments::Inline && !licenses.is_empty() {
+        banner.push_str(&licenses.join("\n"));
+        banner.push('\n');
+    }
+    source.insert_str(0, &banner);
+    if let Some(template) = &options.footer {
+        source.push('\n');
+        source.push_str(&metadata.render(template));
+        source.push('\n');
+    }
+
+    let source_map = match options.source_map {
+        true => {
+            // Lines moved down by the banner.
+            let shift = banner.matches('\n').count() as u32;
+            for (_, loc) in &mut srcmap {
+                loc.line += shift;
+            }
+            let mut buf = vec![];
+            cm.build_source_map(&srcmap).to_writer(&mut buf)?;
+            Some(String::from_utf8(buf)?)
+        }
+        false => None,
+    };
+
+    Ok(BundleOutput {
+        code: source,
+        unminified_size,
+        analysis,
+        source_map,
+        assets: assets.into_inner().unwrap().into_iter().collect(),
+        inputs: inputs.into_inner().unwrap(),
+        licenses,
+        coverage: coverage.into_inner().unwrap(),
+    })
+}
+
+/// Prints a module, stripping whitespace when `options.minify` is set.
+fn emit(
+    cm: &Lrc<SourceMap>,
+    module: &Module,
+    options: &Options,
+    comments: Option<&dyn Comments>,
+    srcmap: Option<&mut Vec<(BytePos, LineCol)>>,
+) -> Result<String> {
     let mut buf = vec![];
 
     {
         let mut cfg = swc_ecma_codegen::Config::default();
         cfg.minify = options.minify;
+        cfg.target = options.target;
 
         let mut emitter = Emitter {
             cfg,
             cm: cm.clone(),
-            comments: None,
-            wr: Box::new(JsWriter::new(cm, "\n", &mut buf, None)),
+            comments,
+            wr: Box::new(JsWriter::new(cm.clone(), "\n", &mut buf, srcmap)),
         };
 
-        emitter.emit_module(&bundle.module)?;
+        emitter.emit_module(module)?;
     }
 
-    // Build source from bytes.
-    let mut source = String::from_utf8(buf).unwrap();
+    Ok(String::from_utf8(buf)?)
+}
 
-    if !options.minify {
-        // Decorate output with the following messages.
-        let messages = [
-            format!("// Dune v{}\n", env!("CARGO_PKG_VERSION")),
-            "// It's not recommended to edit this code manually since it's generated by `dune bundle`\n\n".into()
-        ];
-        messages.iter().rev().for_each(|msg| {
-            source.insert_str(0, msg);
-        });
-    }
+/// Adds side-effect imports of `specifiers` to the top of a module.
+fn prepend_imports(module: &mut Module, specifiers: &[String]) {
+    let imports = specifiers.iter().map(|specifier| {
+        ModuleItem::ModuleDecl(ModuleDecl::Import(ImportDecl {
+            span: DUMMY_SP,
+            specifiers: vec![],
+            src: Box::new(specifier.as_str().into()),
+            type_only: false,
+            with: None,
+            phase: Default::default(),
+        }))
+    });
+    module.body.splice(0..0, imports);
+}
+
+/// Compiles syntax newer than `target` down to it, inlining the helpers the
+/// compat passes need. Must run with `GLOBALS` set.
+fn downlevel(module: Module, target: EsVersion) -> Module {
+    let unresolved_mark = Mark::new();
+    let top_level_mark = Mark::new();
+
+    let mut module = module;
+    module.visit_mut_with(&mut ClearMarks);
+    let program =
+        Program::Module(module).fold_with(&mut resolver(unresolved_mark, top_level_mark, false));
 
-    Ok(source)
+    let comments = None::<SingleThreadedComments>;
+    HELPERS.set(&Helpers::new(false), || {
+        program
+            .fold_with(&mut chain!(
+                Optional::new(
+                    compat::es2022(comments.clone(), Default::default(), unresolved_mark),
+                    target < EsVersion::Es2022
+                ),
+                Optional::new(compat::es2021(), target < EsVersion::Es2021),
+                Optional::new(
+                    compat::es2020(Default::default(), unresolved_mark),
+                    target < EsVersion::Es2020
+                ),
+                Optional::new(compat::es2019(), target < EsVersion::Es2019),
+                Optional::new(
+                    compat::es2018(Default::default()),
+                    target < EsVersion::Es2018
+                ),
+                Optional::new(
+                    compat::es2017(Default::default(), comments.clone(), unresolved_mark),
+                    target < EsVersion::Es2017
+                ),
+                Optional::new(compat::es2016(), target < EsVersion::Es2016),
+                Optional::new(
+                    compat::es2015(unresolved_mark, comments.clone(), Default::default()),
+                    target < EsVersion::Es2015
+                ),
+                Optional::new(compat::es3(true), target <= EsVersion::Es3),
+                inject_helpers(unresolved_mark),
+                hygiene(),
+                fixer(None)
+            ))
+            .expect_module()
+    })
+}
+
+/// Turns the bundle's imports and exports into `require` calls and
+/// assignments to `exports`. Must run with `GLOBALS` set.
+fn to_common_js(module: Module) -> Module {
+    let unresolved_mark = Mark::new();
+    let top_level_mark = Mark::new();
+
+    let mut module = module;
+    module.visit_mut_with(&mut ClearMarks);
+    let program =
+        Program::Module(module).fold_with(&mut resolver(unresolved_mark, top_level_mark, false));
+
+    HELPERS.set(&Helpers::new(false), || {
+        program
+            .fold_with(&mut import_analyzer(ImportInterop::Swc, true))
+            .fold_with(&mut inject_helpers(unresolved_mark))
+            .fold_with(&mut common_js::<SingleThreadedComments>(
+                unresolved_mark,
+                Default::default(),
+                FeatureFlag::default(),
+                None,
+            ))
+            .fold_with(&mut hygiene())
+            .fold_with(&mut fixer(None))
+            .expect_module()
+    })
+}
+
+/// Compresses and mangles the bundled module with swc's minifier.
+fn minify(
+    cm: &Lrc<SourceMap>,
+    module: Module,
+    minifier: &MinifierOptions,
+    comments: &SwcComments,
+) -> Module {
+    let unresolved_mark = Mark::new();
+    let top_level_mark = Mark::new();
+
+    // The bundler already made names unique, so its marks can be dropped
+    // before resolving the whole bundle again.
+    let mut module = module;
+    module.visit_mut_with(&mut ClearMarks);
+    let program =
+        Program::Module(module).fold_with(&mut resolver(unresolved_mark, top_level_mark, false));
+
+    let options = MinifyOptions {
+        compress: minifier.compress.then(|| CompressOptions {
+            keep_fnames: minifier.keep_fn_names,
+            keep_classnames: minifier.keep_class_names,
+            ..Default::default()
+        }),
+        mangle: minifier.mangle.then(|| MangleOptions {
+            keep_fn_names: minifier.keep_fn_names,
+            keep_class_names: minifier.keep_class_names,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let program = optimize(
+        program,
+        cm.clone(),
+        Some(comments),
+        None,
+        &options,
+        &ExtraOptions {
+            unresolved_mark,
+            top_level_mark,
+        },
+    );
+
+    program
+        .fold_with(&mut hygiene())
+        .fold_with(&mut fixer(None))
+        .expect_module()
+}
+
+/// Resets the syntax context of every node.
+struct ClearMarks;
+
+impl VisitMut for ClearMarks {
+    fn visit_mut_syntax_context(&mut self, ctxt: &mut SyntaxContext) {
+        *ctxt = SyntaxContext::empty();
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
     use super::*;
+    use crate::{JsWorker, Req};
     use anyhow::Result;
 
     #[test]
@@ -261,8 +1083,531 @@ mod tests {
         let bundle = run_bundle("fixtures/main.ts", &Default::default())?;
         assert_eq!(
             bundle,
-            r##"(function(){async function execute(name){console.log("Executing lib");return`Hello ${name}!`;}async function main(){console.log("Executing main");console.log(await execute("world"));}return{default:main};})();"##
+            r##"(function(){async function execute(name){return`Hello ${name}!`;}async function main(){console.log("Executing main");console.log(await execute("world"));}return{default:main};})();"##
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_should_analyze_module_graph() -> Result<()> {
+        let output = bundle(
+            "fixtures/main.ts",
+            &Options {
+                analyze: true,
+                ..Default::default()
+            },
+        )?;
+        let analysis = output.analysis.unwrap();
+        assert_eq!(analysis.output_size, output.code.len());
+
+        let [lib, main] = &analysis.modules[..] else {
+            panic!("unexpected modules: {analysis:?}");
+        };
+        assert!(lib.specifier.ends_with("fixtures/lib.ts"));
+        assert_eq!(lib.import_chain.len(), 2);
+        assert_eq!(lib.import_chain[0], "fixtures/main.ts");
+        assert_eq!(
+            lib.exports
+                .iter()
+                .map(|e| (e.name.as_str(), e.kept))
+                .collect::<Vec<_>>(),
+            [("execute", true), ("not_used", false)]
+        );
+        assert!(lib.original_size > lib.output_size && lib.output_size > 0);
+
+        assert_eq!(main.specifier, "fixtures/main.ts");
+        assert_eq!(main.import_chain, ["fixtures/main.ts"]);
+        assert_eq!(main.exports[0].name, "default");
+        assert!(main.exports[0].kept);
+        assert!(lib.output_size + main.output_size <= analysis.output_size);
+
+        let html = analysis.to_html()?;
+        assert!(html.contains(r#"<span class="eliminated">not_used</span>"#));
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_should_cache_transpiled_modules() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let cache_dir = temp_dir.join(".cache");
+        fs::write(
+            temp_dir.join("main.ts"),
+            "export const answer: number = 42;\n",
+        )?;
+        let entry = temp_dir.join("main.ts").display().to_string();
+        let cached = || -> Result<usize> {
+            match fs::read_dir(&cache_dir) {
+                Ok(entries) => Ok(entries.count()),
+                Err(_) => Ok(0),
+            }
+        };
+
+        // source maps point at the sources, which the cache doesn't keep
+        let options = Options {
+            cache_dir: Some(cache_dir.clone()),
+            minify: false,
+            source_map: true,
+            ..Default::default()
+        };
+        let first = bundle(&entry, &options)?;
+        assert_eq!(cached()?, 0);
+        let options = Options {
+            source_map: false,
+            ..options
+        };
+        bundle(&entry, &options)?;
+        assert_eq!(cached()?, 1);
+        let second = bundle(&entry, &options)?;
+        assert_eq!(first.code, second.code);
+
+        // minified and older targets are cached apart
+        bundle(
+            &entry,
+            &Options {
+                minify: true,
+                ..options
+            },
+        )?;
+        assert_eq!(cached()?, 2);
+        bundle(
+            &entry,
+            &Options {
+                target: EsVersion::Es5,
+                cache_dir: Some(cache_dir.clone()),
+                minify: false,
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(cached()?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_support_import_attributes() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("data.json"),
+            r#"{ "greeting": "`hello` ${name} \\n", "max-size": 10, "default": true }"#,
+        )?;
+        fs::write(temp_dir.join("query.sql"), "SELECT `id` FROM users;\n")?;
+        fs::write(temp_dir.join("raw.bin"), [0u8, 1, 2, 255])?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            import data, { greeting } from "./data.json" with { type: "json" };
+            import query from "./query.sql" with { type: "text" };
+            import raw from "./raw.bin" with { type: "bytes" };
+
+            async function hello(req: any) {
+                return {
+                    status: 200,
+                    headers: {},
+                    body: JSON.stringify([greeting, data["max-size"], data.default, query, Array.from(raw)]),
+                };
+            }
+
+            export { hello };
+            "#,
+        )?;
+
+        let bundle = run_bundle(
+            &temp_dir.join("main.ts").display().to_string(),
+            &Default::default(),
+        )?;
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com")
+            .headers(HashMap::new())
+            .build();
+        let res = JsWorker::try_new(&bundle)?.run_http("hello", req)?;
+        assert_eq!(
+            res.body.as_deref(),
+            Some(r#"["`hello` ${name} \\n",10,true,"SELECT `id` FROM users;\n",[0,1,2,255]]"#)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_support_output_formats() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("greeting.ts"),
+            "export const greeting = (name: string) => `hello ${name}`;",
+        )?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            import { greeting } from "./greeting.ts";
+
+            export async function hello(req: any) {
+                return { status: 200, headers: {}, body: greeting("dino") };
+            }
+
+            export default hello;
+            "#,
+        )?;
+
+        for (format, expected) in [
+            (OutputFormat::Iife, "return{"),
+            (OutputFormat::Esm, "export{"),
+            (OutputFormat::Cjs, "exports"),
+        ] {
+            let options = Options {
+                format,
+                ..Default::default()
+            };
+            let bundle = run_bundle(&temp_dir.join("main.ts").display().to_string(), &options)?;
+            assert!(bundle.contains(expected), "{format:?}: {bundle}");
+
+            let req = Req::builder()
+                .method("GET")
+                .url("https://example.com")
+                .headers(HashMap::new())
+                .build();
+            let worker = JsWorker::try_new_with_format(&bundle, format)?;
+            let res = worker.run_http("hello", req)?;
+            assert_eq!(res.body.as_deref(), Some("hello dino"), "{format:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_downlevel_to_target() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("polyfill.js"),
+            "globalThis.polyfilled = true;",
+        )?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            class Counter {
+                #count = 0;
+                static step = 2;
+                increment() {
+                    this.#count += Counter.step;
+                    return this.#count;
+                }
+            }
+
+            const config: { retries?: { max?: number } } = {};
+
+            export async function hello(req: any) {
+                const counter = new Counter();
+                await Promise.resolve();
+                const max = config.retries?.max ?? 3;
+                const values = [counter.increment(), counter.increment(), max];
+                return {
+                    status: 200,
+                    headers: {},
+                    body: `${values.map((v) => v * 10)} ${(globalThis as any).polyfilled}`,
+                };
+            }
+            "#,
+        )?;
+
+        let options = Options {
+            target: EsVersion::Es5,
+            polyfills: vec!["./polyfill.js".into()],
+            ..Default::default()
+        };
+        let bundle = run_bundle(&temp_dir.join("main.ts").display().to_string(), &options)?;
+        for syntax in [
+            "class Counter",
+            "#count",
+            "async function",
+            "await ",
+            "?.",
+            "??",
+            "=>",
+            "`",
+            "const ",
+        ] {
+            assert!(!bundle.contains(syntax), "{syntax}: {bundle}");
+        }
+
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com")
+            .headers(HashMap::new())
+            .build();
+        let res = JsWorker::try_new(&bundle)?.run_http("hello", req)?;
+        assert_eq!(res.body.as_deref(), Some("20,40,30 true"));
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_should_keep_legal_comments() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("vendor.js"),
+            r#"/*! vendor v1.0.0 | MIT */
+            // @license Apache-2.0
+            // an ordinary comment
+            export function factory(id) {
+                globalThis.created = (globalThis.created || 0) + id;
+                return id;
+            }
+            "#,
+        )?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            /*! main | MIT */
+            import { factory } from "./vendor.js";
+
+            const unused = /* @__PURE__ */ factory(100);
+            export const used = factory(1);
+            "#,
+        )?;
+        let entry = temp_dir.join("main.ts").display().to_string();
+
+        let output = bundle(
+            &entry,
+            &Options {
+                minifier: Some(MinifierOptions {
+                    mangle: false,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            output.licenses,
+            [
+                "/*! main | MIT */",
+                "/*! vendor v1.0.0 | MIT */",
+                "// @license Apache-2.0"
+            ]
+        );
+        assert!(output.code.starts_with(&output.licenses.join("\n")));
+        assert!(!output.code.contains("ordinary"), "{}", output.code);
+        // the annotated call was dropped by the minifier
+        assert!(!output.code.contains("100"), "{}", output.code);
+
+        for legal_comments in [LegalComments::External, LegalComments::None] {
+            let output = bundle(
+                &entry,
+                &Options {
+                    legal_comments,
+                    ..Default::default()
+                },
+            )?;
+            assert_eq!(output.licenses.len(), 3);
+            assert!(!output.code.contains("MIT"), "{}", output.code);
+            assert!(output.code.contains("/* @__PURE__ */"), "{}", output.code);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_add_build_metadata() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            export async function hello(req: any) {
+                return {
+                    status: 200,
+                    headers: {},
+                    body: `${__DINO_BUILD__.name}@${__DINO_BUILD__.version}`,
+                };
+            }
+            "#,
+        )?;
+
+        let options = Options {
+            metadata: Some(BuildMetadata {
+                name: "demo".into(),
+                version: Some("1.2.0".into()),
+                commit: Some("abc1234".into()),
+                built_at: "2024-08-01T12:00:00Z".into(),
+            }),
+            banner: Some("/* {name} v{version} ({commit}) */".into()),
+            footer: Some("// built at {built_at}".into()),
+            ..Default::default()
+        };
+        let bundle = run_bundle(&temp_dir.join("main.ts").display().to_string(), &options)?;
+        assert!(
+            bundle.starts_with("/* demo v1.2.0 (abc1234) */\n"),
+            "{bundle}"
+        );
+        assert!(
+            bundle.ends_with("\n// built at 2024-08-01T12:00:00Z\n"),
+            "{bundle}"
+        );
+
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com")
+            .headers(HashMap::new())
+            .build();
+        let res = JsWorker::try_new(&bundle)?.run_http("hello", req)?;
+        assert_eq!(res.body.as_deref(), Some("demo@1.2.0"));
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_support_asset_imports() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let build_dir = temp_dir.join("build");
+        fs::write(temp_dir.join("index.html"), "<h1>`Hello` ${name}</h1>")?;
+        fs::write(temp_dir.join("logo.png"), [137u8, 80, 78, 71])?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            import page from "./index.html";
+            import logo from "./logo.png";
+
+            async function hello(req: any) {
+                return {
+                    status: 200,
+                    headers: {},
+                    body: JSON.stringify([page, typeof logo === "string" ? logo : Array.from(logo)]),
+                };
+            }
+
+            export { hello };
+            "#,
+        )?;
+        let entry = temp_dir.join("main.ts").display().to_string();
+        let req = || {
+            Req::builder()
+                .method("GET")
+                .url("https://example.com")
+                .headers(HashMap::new())
+                .build()
+        };
+
+        // Binary assets are inlined by default.
+        let bundle = run_bundle(&entry, &Default::default())?;
+        let res = JsWorker::try_new(&bundle)?.run_http("hello", req())?;
+        assert_eq!(
+            res.body.as_deref(),
+            Some(r#"["<h1>`Hello` ${name}</h1>",[137,80,78,71]]"#)
+        );
+
+        // Or emitted as hashed files next to the bundle.
+        let options = Options {
+            asset_dir: Some(build_dir.clone()),
+            ..Default::default()
+        };
+        let bundle = run_bundle(&entry, &options)?;
+        let res = JsWorker::try_new(&bundle)?.run_http("hello", req())?;
+        let hash = blake3::hash(&[137u8, 80, 78, 71]).to_hex();
+        let file_name = format!("logo.{}.png", &hash[..8]);
+        assert_eq!(
+            res.body.unwrap(),
+            format!(r#"["<h1>`Hello` ${{name}}</h1>","./{file_name}"]"#)
+        );
+        assert!(build_dir.join(file_name).is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_apply_define() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            function shadowed(__VERSION__: string) {
+                return __VERSION__;
+            }
+
+            async function main() {
+                if (process.env.NODE_ENV !== "production") {
+                    console.log("debug build");
+                }
+                return shadowed("local") + __VERSION__;
+            }
+
+            export default main;
+            "#,
+        )?;
+
+        let options = Options {
+            define: BTreeMap::from([
+                ("process.env.NODE_ENV".into(), r#""production""#.into()),
+                ("__VERSION__".into(), r#""1.0.0""#.into()),
+            ]),
+            ..Default::default()
+        };
+        let bundle = run_bundle(&temp_dir.join("main.ts").display().to_string(), &options)?;
+        assert_eq!(
+            bundle,
+            r#"(function(){function shadowed(__VERSION__){return __VERSION__;}async function main(){return shadowed("local")+"1.0.0";}return{default:main};})();"#
         );
         Ok(())
     }
+
+    #[test]
+    fn bundle_should_minify() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            r#"
+            class Greeter {
+                greet(name: string) {
+                    const greeting = "hello " + name;
+                    return greeting;
+                }
+            }
+
+            function handler(request: { url: string }) {
+                const longVariableName = new Greeter().greet(request.url);
+                if (false) {
+                    console.log("unreachable");
+                }
+                return { status: 200, body: longVariableName + (1 + 2) };
+            }
+
+            export { handler };
+            "#,
+        )?;
+        let entry = temp_dir.join("main.ts").display().to_string();
+
+        let output = bundle(
+            &entry,
+            &Options {
+                minifier: Some(MinifierOptions::default()),
+                ..Default::default()
+            },
+        )?;
+        assert!(output.unminified_size.unwrap() > output.code.len());
+        assert!(!output.code.contains("longVariableName"));
+        assert!(!output.code.contains("unreachable"));
+
+        let output = bundle(
+            &entry,
+            &Options {
+                minifier: Some(MinifierOptions {
+                    keep_fn_names: true,
+                    keep_class_names: true,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+        assert!(output.code.contains("function handler("));
+        assert!(output.code.contains("class Greeter"));
+        Ok(())
+    }
+
+    #[test]
+    fn run_bundle_should_reject_invalid_json() -> Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        fs::write(temp_dir.join("data.json"), "{\n  \"a\": 1,\n}")?;
+        fs::write(
+            temp_dir.join("main.ts"),
+            "import data from './data.json';\nexport default data;\n",
+        )?;
+
+        let err = run_bundle(
+            &temp_dir.join("main.ts").display().to_string(),
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("line 3 column 1"), "{err:#}");
+        Ok(())
+    }
 }
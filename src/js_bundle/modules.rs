@@ -1,11 +1,15 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde_json::Value;
-use std::{collections::HashMap, env, path::Path};
+use std::{collections::HashMap, env, fs, path::Path, str::FromStr};
 use url::Url;
 
-use super::loaders::{CoreModuleLoader, FsModuleLoader, ModuleLoader, UrlModuleLoader};
+use super::loaders::{
+    wrap_bytes, wrap_json, wrap_text, CoreModuleLoader, FsModuleLoader, ModuleLoader,
+    UrlModuleLoader,
+};
+use super::media_types::MediaType;
 
 pub type ModulePath = String;
 pub type ModuleSource = String;
@@ -105,10 +109,64 @@ impl ImportMap {
     }
 }
 
-/// Loads an import using the appropriate loader.
-pub fn load_import(specifier: &str, skip_cache: bool) -> Result<ModuleSource> {
+/// Marker carrying a specifier's import type through module resolution.
+const IMPORT_TYPE_MARKER: &str = "#dino-import-type=";
+
+/// The `type` of an import attribute, e.g. `with { type: "json" }`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportType {
+    #[default]
+    JavaScript,
+    Json,
+    Text,
+    Bytes,
+}
+
+impl ImportType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportType::JavaScript => "javascript",
+            ImportType::Json => "json",
+            ImportType::Text => "text",
+            ImportType::Bytes => "bytes",
+        }
+    }
+
+    /// Tags a specifier with this import type.
+    pub fn tag(&self, specifier: &str) -> String {
+        match self {
+            ImportType::JavaScript => specifier.to_string(),
+            _ => format!("{specifier}{IMPORT_TYPE_MARKER}{}", self.as_str()),
+        }
+    }
+
+    /// Splits a tagged specifier into the original specifier and its import type.
+    pub fn untag(specifier: &str) -> Result<(&str, ImportType)> {
+        match specifier.rsplit_once(IMPORT_TYPE_MARKER) {
+            Some((specifier, import_type)) => Ok((specifier, import_type.parse()?)),
+            None => Ok((specifier, ImportType::JavaScript)),
+        }
+    }
+}
+
+impl FromStr for ImportType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value {
+            "javascript" => ImportType::JavaScript,
+            "json" => ImportType::Json,
+            "text" => ImportType::Text,
+            "bytes" => ImportType::Bytes,
+            _ => bail!("Unsupported import attribute type \"{value}\""),
+        })
+    }
+}
+
+/// Chooses the loader for an already resolved specifier.
+fn loader_for(specifier: &str, skip_cache: bool) -> Box<dyn ModuleLoader> {
     // Look the params and choose a loader.
-    let loader: Box<dyn ModuleLoader> = match (
+    match (
         CORE_MODULES.contains_key(specifier),
         WINDOWS_REGEX.is_match(specifier),
         Url::parse(specifier).is_ok(),
@@ -117,10 +175,53 @@ pub fn load_import(specifier: &str, skip_cache: bool) -> Result<ModuleSource> {
         (_, true, _) => Box::new(FsModuleLoader),
         (_, _, true) => Box::new(UrlModuleLoader { skip_cache }),
         _ => Box::new(FsModuleLoader),
-    };
+    }
+}
+
+/// Identifies the contents of a resolved import, for build caching: a hash
+/// of the file a local import refers to, and the specifier itself for core
+/// modules and URLs, which are assumed to be immutable.
+pub fn import_hash(specifier: &str) -> Result<String> {
+    let (specifier, _) = ImportType::untag(specifier)?;
+    match (
+        CORE_MODULES.contains_key(specifier),
+        WINDOWS_REGEX.is_match(specifier),
+        Url::parse(specifier).is_ok(),
+    ) {
+        (true, _, _) | (_, false, true) => Ok(specifier.to_string()),
+        _ => {
+            let file = FsModuleLoader
+                .find_file(Path::new(specifier))
+                .ok_or_else(|| anyhow!("Module not found \"{specifier}\""))?;
+            Ok(blake3::hash(&fs::read(file)?).to_string())
+        }
+    }
+}
+
+/// Loads the raw contents of an import using the appropriate loader.
+pub fn load_import_bytes(specifier: &str, skip_cache: bool) -> Result<Vec<u8>> {
+    loader_for(specifier, skip_cache).load_bytes(specifier)
+}
+
+/// Loads an import using the appropriate loader, see [`ModuleLoader::load`].
+pub fn load_import(
+    specifier: &str,
+    import_type: ImportType,
+    skip_cache: bool,
+) -> Result<(ModuleSource, MediaType)> {
+    let loader = loader_for(specifier, skip_cache);
 
-    // Load module.
-    loader.load(specifier)
+    // Load module, honouring its import attributes.
+    let source = match import_type {
+        ImportType::JavaScript => return loader.load(specifier),
+        ImportType::Json => wrap_json(
+            specifier,
+            &String::from_utf8(loader.load_bytes(specifier)?)?,
+        )?,
+        ImportType::Text => wrap_text(&String::from_utf8(loader.load_bytes(specifier)?)?),
+        ImportType::Bytes => wrap_bytes(&loader.load_bytes(specifier)?),
+    };
+    Ok((source, MediaType::JavaScript))
 }
 
 /// Resolves an import using the appropriate loader.
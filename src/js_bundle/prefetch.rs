@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use url::Url;
+
+use super::loaders::FsModuleLoader;
+use super::media_types::MediaType;
+use super::modules::load_import_bytes;
+use super::modules::resolve_import;
+use super::modules::ImportMap;
+
+/// Downloads running at once.
+const MAX_DOWNLOADS: usize = 8;
+
+lazy_static! {
+    // Specifiers of static imports and re-exports, e.g. `import "./a"`,
+    // `import { a } from "./a"` or `export * from "./a"`.
+    static ref IMPORT_REGEX: Regex = Regex::new(
+        r#"(?m)(?:^|[;}\s])(?:import|export)\s*(?:[\w$*{},\s]*?\bfrom\s*)?["']([^"'\r\n]+)["']"#
+    )
+    .unwrap();
+}
+
+/// Downloads the URL modules of a graph ahead of bundling, several at a
+/// time, rather than one by one as the bundler reaches them. The graph is
+/// walked from `entries` by scanning the sources for imports, level by level,
+/// and returns the URLs that were downloaded.
+///
+/// This is best-effort: imports the scan misses are downloaded when loaded.
+pub fn prefetch_urls(
+    entries: &[String],
+    import_map: Option<&ImportMap>,
+    skip_cache: bool,
+) -> HashSet<String> {
+    let mut seen: HashSet<String> = entries.iter().cloned().collect();
+    let mut fetched = HashSet::new();
+    let mut level = entries.to_vec();
+
+    while !level.is_empty() {
+        let (urls, files): (Vec<_>, Vec<_>) = level.into_iter().partition(|module| is_url(module));
+        let mut sources = download(&urls, skip_cache);
+        fetched.extend(sources.iter().map(|(url, _)| url.clone()));
+        sources.extend(files.into_iter().filter_map(|file| {
+            let path = FsModuleLoader.find_file(Path::new(&file))?;
+            match MediaType::from_path(&path.to_string_lossy()) {
+                MediaType::JavaScript | MediaType::Jsx | MediaType::TypeScript | MediaType::Tsx => {
+                    Some((file, fs::read(path).ok()?))
+                }
+                _ => None,
+            }
+        }));
+
+        level = vec![];
+        for (module, bytes) in sources {
+            let Ok(source) = String::from_utf8(bytes) else {
+                continue;
+            };
+            for captures in IMPORT_REGEX.captures_iter(&source) {
+                // core modules and bare specifiers don't resolve
+                let Ok(import) =
+                    resolve_import(Some(&module), &captures[1], true, import_map.cloned())
+                else {
+                    continue;
+                };
+                if seen.insert(import.clone()) {
+                    level.push(import);
+                }
+            }
+        }
+    }
+    fetched
+}
+
+fn is_url(specifier: &str) -> bool {
+    Url::parse(specifier).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Downloads `urls` concurrently, leaving out the ones that fail, for the
+/// loader to report.
+fn download(urls: &[String], skip_cache: bool) -> Vec<(String, Vec<u8>)> {
+    thread::scope(|scope| {
+        urls.chunks(MAX_DOWNLOADS)
+            .flat_map(|chunk| {
+                let downloads: Vec<_> = chunk
+                    .iter()
+                    .map(|url| scope.spawn(move || load_import_bytes(url, skip_cache)))
+                    .collect();
+                chunk
+                    .iter()
+                    .zip(downloads)
+                    .filter_map(|(url, download)| Some((url.clone(), download.join().ok()?.ok()?)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn prefetch_urls_should_download_the_graph() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let temp_dir = assert_fs::TempDir::new().unwrap();
+        fs::write(
+            temp_dir.join("main.ts"),
+            format!("import {{ a }} from './lib';\nimport '{base}/b.js';\nconsole.log(a);\n"),
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.join("lib.ts"),
+            format!("export {{ a }} from '{base}/a.js';\n"),
+        )
+        .unwrap();
+
+        // serves each module once, so loading them again would fail
+        let server = thread::spawn(move || {
+            for _ in 0..3 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let body = match request.split(' ').nth(1).unwrap() {
+                    "/a.js" => "import { c } from './c.js';\nexport const a = c;\n",
+                    "/b.js" => "globalThis.b = 1;\n",
+                    _ => "export const c = 'c';\n",
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/javascript\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        let entry = temp_dir.join("main.ts").display().to_string();
+        let bundle = crate::bundle(&entry, &Default::default()).unwrap();
+        server.join().unwrap();
+        assert!(bundle.code.contains("\"c\""), "{}", bundle.code);
+        assert!(bundle.code.contains("globalThis.b=1"), "{}", bundle.code);
+    }
+}
@@ -1,95 +1,388 @@
-use anyhow::bail;
+use anyhow::anyhow;
 use anyhow::Result;
 use lazy_static::lazy_static;
 use regex::Regex;
+use swc_common::comments::Comments;
+use swc_common::comments::SingleThreadedComments;
 use swc_common::errors::ColorConfig;
 use swc_common::errors::Handler;
 use swc_common::sync::Lrc;
 use swc_common::FileName;
 use swc_common::Globals;
 use swc_common::Mark;
+use swc_common::SourceFile;
 use swc_common::SourceMap;
+use swc_common::DUMMY_SP;
 use swc_common::GLOBALS;
+use swc_ecma_ast::ClassExpr;
+use swc_ecma_ast::Decl;
+use swc_ecma_ast::DefaultDecl;
+use swc_ecma_ast::EsVersion;
+use swc_ecma_ast::Expr;
+use swc_ecma_ast::ExprStmt;
+use swc_ecma_ast::ImportDecl;
+use swc_ecma_ast::ImportSpecifier;
+use swc_ecma_ast::Module;
+use swc_ecma_ast::ModuleDecl;
+use swc_ecma_ast::ModuleExportName;
+use swc_ecma_ast::ModuleItem;
+use swc_ecma_ast::Pat;
+use swc_ecma_ast::Program;
+use swc_ecma_ast::Stmt;
+use swc_ecma_ast::VarDecl;
+use swc_ecma_ast::VarDeclKind;
+use swc_ecma_ast::VarDeclarator;
 use swc_ecma_codegen::text_writer::JsWriter;
 use swc_ecma_codegen::Emitter;
-use swc_ecma_parser::lexer::Lexer;
-use swc_ecma_parser::Parser;
-use swc_ecma_parser::StringInput;
+use swc_ecma_parser::parse_file_as_module;
+use swc_ecma_parser::EsSyntax;
 use swc_ecma_parser::Syntax;
 use swc_ecma_parser::TsSyntax;
 use swc_ecma_transforms_base::fixer::fixer;
-use swc_ecma_transforms_base::hygiene::hygiene;
 use swc_ecma_transforms_base::resolver;
-
+use swc_ecma_transforms_react::react;
+use swc_ecma_transforms_react::Options as JsxOptions;
 use swc_ecma_transforms_typescript::strip;
+use swc_ecma_transforms_typescript::typescript;
+use swc_ecma_transforms_typescript::Config as TsConfig;
 use swc_ecma_visit::FoldWith;
 
+use super::media_types::MediaType;
+use super::modules::CORE_MODULES;
+
+/// Global the exports of the modules loaded into a worker are kept in, by
+/// name, see [`compile_script`].
+pub const MODULES_GLOBAL: &str = "$$modules";
+
 lazy_static! {
     static ref PRAGMA_REGEX: Regex = Regex::new(r"@jsx\s+([^\s]+)").unwrap();
 }
 
-pub struct TypeScript;
+/// Parses a module into the given source map, compiling TypeScript and JSX
+/// syntax away. The resulting AST is handed to the bundler as is, so it is
+/// never printed and parsed again. Comments are collected into `comments`.
+/// Must run with `GLOBALS` set.
+pub fn parse_module(
+    cm: &Lrc<SourceMap>,
+    fm: &SourceFile,
+    media_type: MediaType,
+    comments: Option<&dyn Comments>,
+) -> Result<Module> {
+    let (typescript, jsx) = match media_type {
+        MediaType::TypeScript => (true, false),
+        MediaType::Tsx => (true, true),
+        MediaType::Jsx => (false, true),
+        _ => (false, false),
+    };
 
-impl TypeScript {
-    /// Compiles TypeScript code into JavaScript.
-    pub fn compile(filename: Option<&str>, source: &str) -> Result<String> {
-        let globals = Globals::default();
-        let cm: Lrc<SourceMap> = Default::default();
-        let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
+    let syntax = match typescript {
+        true => Syntax::Typescript(TsSyntax {
+            tsx: jsx,
+            decorators: true,
+            no_early_errors: true,
+            ..Default::default()
+        }),
+        false => Syntax::Es(EsSyntax {
+            jsx,
+            import_attributes: true,
+            ..Default::default()
+        }),
+    };
 
-        let filename = match filename {
-            Some(filename) => Lrc::new(FileName::Custom(filename.into())),
-            None => Lrc::new(FileName::Anon),
-        };
+    let module = parse_file_as_module(fm, syntax, EsVersion::latest(), comments, &mut vec![])
+        .map_err(|e| {
+            let handler =
+                Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
+            e.into_diagnostic(&handler).emit();
+            anyhow!("Failed to parse \"{}\"", fm.name)
+        })?;
 
-        let fm = cm.new_source_file(filename, source.into());
-
-        // Initialize the TypeScript lexer.
-        let lexer = Lexer::new(
-            Syntax::Typescript(TsSyntax {
-                tsx: true,
-                decorators: true,
-                no_early_errors: true,
-                ..Default::default()
-            }),
-            Default::default(),
-            StringInput::from(&*fm),
-            None,
-        );
+    if !typescript && !jsx {
+        return Ok(module);
+    }
 
-        let mut parser = Parser::new_from(lexer);
+    let unresolved_mark = Mark::new();
+    let top_level_mark = Mark::new();
+    // The TypeScript transform only accepts whole programs.
+    let mut program = Program::Module(module).fold_with(&mut resolver(
+        unresolved_mark,
+        top_level_mark,
+        typescript,
+    ));
 
-        let program = match parser
-            .parse_program()
-            .map_err(|e| e.into_diagnostic(&handler).emit())
-        {
-            Ok(module) => module,
-            Err(_) => bail!("TypeScript compilation failed."),
+    // JSX goes first, so that imports of the JSX factory are used when the
+    // TypeScript pass drops unused imports.
+    if jsx {
+        // Use a custom factory when the source specifies an `@jsx` pragma.
+        let options = JsxOptions {
+            pragma: PRAGMA_REGEX
+                .captures(&fm.src)
+                .map(|captures| captures[1].to_string()),
+            ..Default::default()
         };
+        program = program.fold_with(&mut react::<SingleThreadedComments>(
+            cm.clone(),
+            None,
+            options,
+            top_level_mark,
+            unresolved_mark,
+        ));
+    }
+    if typescript {
+        program = program.fold_with(&mut strip(unresolved_mark, top_level_mark));
+    }
 
-        // This is where we're gonna store the JavaScript output.
-        let mut buffer = vec![];
+    Ok(program.fold_with(&mut fixer(None)).expect_module())
+}
 
-        GLOBALS.set(&globals, || {
-            // Apply the rest SWC transforms to generated code.
-            let program = program
-                .fold_with(&mut resolver(Mark::new(), Mark::new(), true))
-                .fold_with(&mut strip(Mark::new(), Mark::new()))
-                .fold_with(&mut hygiene())
-                .fold_with(&mut fixer(None));
-
-            {
-                let mut emitter = Emitter {
-                    cfg: swc_ecma_codegen::Config::default(),
-                    cm: cm.clone(),
-                    comments: None,
-                    wr: JsWriter::new(cm, "\n", &mut buffer, None),
+/// TypeScript typed into `dino repl`, compiled to a script.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Script {
+    pub code: String,
+    /// Modules of the project the script imports, which must be loaded
+    /// into `MODULES_GLOBAL` under these specifiers before it runs.
+    pub imports: Vec<String>,
+}
+
+/// Compiles TypeScript to a script, which unlike a module can be evaluated
+/// piece by piece in the same context. Imports become variables, with core
+/// modules imported dynamically and others taken from `MODULES_GLOBAL`, and
+/// exports are dropped, keeping their declarations. Top-level `const`, `let`
+/// and `class` declarations become `var`s, so that input can be run again.
+pub fn compile_script(source: &str) -> Result<Script> {
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(Lrc::new(FileName::Custom("repl".into())), source.into());
+    let syntax = Syntax::Typescript(TsSyntax {
+        no_early_errors: true,
+        ..Default::default()
+    });
+    let module = parse_file_as_module(&fm, syntax, EsVersion::latest(), None, &mut vec![])
+        .map_err(|e| anyhow!("{}", e.kind().msg()))?;
+
+    let module = GLOBALS.set(&Globals::default(), || {
+        let unresolved_mark = Mark::new();
+        let top_level_mark = Mark::new();
+        // Imports are kept even when unused, since later input may use them.
+        let config = TsConfig {
+            verbatim_module_syntax: true,
+            no_empty_export: true,
+            ..Default::default()
+        };
+        Program::Module(module)
+            .fold_with(&mut resolver(unresolved_mark, top_level_mark, true))
+            .fold_with(&mut typescript(config, unresolved_mark, top_level_mark))
+            .fold_with(&mut fixer(None))
+            .expect_module()
+    });
+
+    let mut imports = vec![];
+    let mut code = String::new();
+    let mut body = Module {
+        span: module.span,
+        body: vec![],
+        shebang: None,
+    };
+    for item in module.body {
+        match item {
+            ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => {
+                let specifier = import.src.value.to_string();
+                let quoted = serde_json::to_string(&specifier)?;
+                let source = match CORE_MODULES.contains_key(specifier.as_str()) {
+                    true => format!("await import({quoted})"),
+                    false => {
+                        imports.push(specifier);
+                        format!("globalThis.{MODULES_GLOBAL}[{quoted}]")
+                    }
                 };
+                code.push_str(&import_bindings(&import, &source)?);
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                body.body.push(ModuleItem::Stmt(redeclarable(export.decl)));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => {
+                let expr = match export.decl {
+                    DefaultDecl::Class(class) => Expr::Class(class),
+                    DefaultDecl::Fn(function) => Expr::Fn(function),
+                    DefaultDecl::TsInterfaceDecl(_) => continue,
+                };
+                body.body.push(expr_stmt(Box::new(expr)));
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(export)) => {
+                body.body.push(expr_stmt(export.expr));
+            }
+            ModuleItem::ModuleDecl(_) => {}
+            ModuleItem::Stmt(Stmt::Decl(decl)) => {
+                body.body.push(ModuleItem::Stmt(redeclarable(decl)))
+            }
+            ModuleItem::Stmt(stmt) => body.body.push(ModuleItem::Stmt(stmt)),
+        }
+    }
+
+    let mut buffer = vec![];
+    Emitter {
+        cfg: Default::default(),
+        cm: cm.clone(),
+        comments: None,
+        wr: JsWriter::new(cm, "\n", &mut buffer, None),
+    }
+    .emit_module(&body)?;
+    code.push_str(std::str::from_utf8(&buffer)?);
+    Ok(Script { code, imports })
+}
 
-                emitter.emit_program(&program).unwrap();
+/// Declares the bindings of an import as variables, so that importing again
+/// doesn't fail.
+fn import_bindings(import: &ImportDecl, source: &str) -> Result<String> {
+    let mut names = vec![];
+    let mut namespace = None;
+    for specifier in &import.specifiers {
+        match specifier {
+            ImportSpecifier::Named(named) => {
+                let imported = match &named.imported {
+                    Some(ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                    Some(ModuleExportName::Str(s)) => serde_json::to_string(&*s.value)?,
+                    None => named.local.sym.to_string(),
+                };
+                names.push(format!("{imported}: {}", named.local.sym));
+            }
+            ImportSpecifier::Default(default) => {
+                names.push(format!("default: {}", default.local.sym));
             }
-        });
+            ImportSpecifier::Namespace(ns) => namespace = Some(ns.local.sym.to_string()),
+        }
+    }
+    Ok(match (namespace, names.is_empty()) {
+        (Some(namespace), true) => format!("var {namespace} = {source};\n"),
+        (Some(namespace), false) => format!(
+            "var {namespace} = {source};\nvar {{ {} }} = {namespace};\n",
+            names.join(", ")
+        ),
+        (None, false) => format!("var {{ {} }} = {source};\n", names.join(", ")),
+        (None, true) => format!("{source};\n"),
+    })
+}
+
+/// Turns lexical declarations into `var`s, which unlike them can be declared
+/// again by later input, e.g. `class A {}` into `var A = class A {}`.
+fn redeclarable(decl: Decl) -> Stmt {
+    let decl = match decl {
+        Decl::Var(mut var) => {
+            var.kind = VarDeclKind::Var;
+            Decl::Var(var)
+        }
+        Decl::Class(class) => Decl::Var(Box::new(VarDecl {
+            span: DUMMY_SP,
+            ctxt: Default::default(),
+            kind: VarDeclKind::Var,
+            declare: false,
+            decls: vec![VarDeclarator {
+                span: DUMMY_SP,
+                name: Pat::Ident(class.ident.clone().into()),
+                init: Some(Box::new(Expr::Class(ClassExpr {
+                    ident: Some(class.ident),
+                    class: class.class,
+                }))),
+                definite: false,
+            }],
+        })),
+        decl => decl,
+    };
+    Stmt::Decl(decl)
+}
+
+fn expr_stmt(expr: Box<Expr>) -> ModuleItem {
+    ModuleItem::Stmt(Stmt::Expr(ExprStmt {
+        span: DUMMY_SP,
+        expr,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use swc_common::FileName;
+    use swc_common::Globals;
+    use swc_common::GLOBALS;
+    use swc_ecma_codegen::text_writer::JsWriter;
+    use swc_ecma_codegen::Emitter;
+
+    use super::*;
+
+    fn transpile(name: &str, source: &str) -> Result<String> {
+        let cm: Lrc<SourceMap> = Default::default();
+        let fm = cm.new_source_file(Lrc::new(FileName::Custom(name.into())), source.into());
+
+        let module = GLOBALS.set(&Globals::default(), || {
+            parse_module(&cm, &fm, MediaType::from_path(name), None)
+        })?;
 
-        Ok(String::from_utf8_lossy(&buffer).to_string())
+        let mut buffer = vec![];
+        Emitter {
+            cfg: Default::default(),
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm, "\n", &mut buffer, None),
+        }
+        .emit_module(&module)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    #[test]
+    fn parse_module_should_compile_typescript_and_jsx() -> Result<()> {
+        let tests = [
+            (
+                "lib.ts",
+                "import type { A } from './a.ts';\nexport const id = <T,>(x: T): T => x as T;",
+                "export const id = (x)=>x;",
+            ),
+            (
+                "app.tsx",
+                "const name: string = 'dino';\nexport const app = <div>{name}</div>;",
+                "React.createElement(\"div\", null, name)",
+            ),
+            (
+                "page.jsx",
+                "/** @jsx h */\nexport const page = <p>hello</p>;",
+                "h(\"p\", null, \"hello\")",
+            ),
+        ];
+
+        for (file, source, expected) in tests {
+            let output = transpile(file, source)?;
+            assert!(output.contains(expected), "{output}");
+            assert!(!output.contains("import"), "{output}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_module_should_report_syntax_errors() {
+        let err = transpile("broken.ts", "export const = 1;").unwrap_err();
+        assert!(err.to_string().contains("broken.ts"), "{err}");
+    }
+
+    #[test]
+    fn compile_script_should_turn_imports_into_variables() -> Result<()> {
+        let script = compile_script(
+            "import assert, { ok as check } from 'assert';\n\
+             import * as lib from './lib.ts';\n\
+             import type { Req } from './types.ts';\n\
+             export const answer: number = await lib.answer();\n\
+             answer as number;",
+        )?;
+        assert_eq!(script.imports, vec!["./lib.ts"]);
+        assert_eq!(
+            script.code,
+            "var { default: assert, ok: check } = await import(\"assert\");\n\
+             var lib = globalThis.$$modules[\"./lib.ts\"];\n\
+             var answer = await lib.answer();\n\
+             answer;\n"
+        );
+
+        let script = compile_script("let a = 1, b = a;\nexport class A {}\nfunction f() {}")?;
+        assert_eq!(
+            script.code,
+            "var a = 1, b = a;\nvar A = class A {\n};\nfunction f() {}\n"
+        );
+        Ok(())
     }
 }
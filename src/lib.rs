@@ -1,5 +1,9 @@
 mod cli;
+mod config;
+mod engine;
 mod js_bundle;
 
 pub use cli::*;
+pub use config::*;
+pub use engine::*;
 pub use js_bundle::*;